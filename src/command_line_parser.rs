@@ -6,20 +6,128 @@
 use clap::{command, Arg, ArgAction, Command};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use rust_for_it::registered_scheme_of;
 
-use super::network::TimeoutSeconds;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use super::log_targets::parse_log_target;
+use super::logging::{parse_line_template, DEFAULT_LINE_TEMPLATE};
+
+pub(crate) type TimeoutSeconds = u64;
+
+/// Upper bound for the number of services a single --service argument
+/// may expand to, to catch typos like `{1..50000}` early
+const MAX_EXPANDED_SERVICES: usize = 256;
 
 fn parse_service_syntax(text: &str) -> Result<String, String> {
     // Note: We are not using .to_socket_addrs() here because that
     //       would do DNS queries, already.
-    static PATTERN: &str = r"^(\[[0-9a-fA-F.:]+\]|[^:]+):([1-9][0-9]{0,4})$";
+    static PATTERN: &str = r"^(\[[0-9a-fA-F.:]+(%[0-9a-zA-Z_.-]+)?\]|[^:]+):([1-9][0-9]{0,4})$";
     static MATCHER: Lazy<Regex> = Lazy::new(|| Regex::new(PATTERN).unwrap());
-    match MATCHER.find(text) {
+    let host_and_port = match text.get(..6) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("tcp://") => &text[6..],
+        _ => text,
+    };
+    match MATCHER.find(host_and_port) {
         Some(_) => Ok(text.to_string()),
         _ => Err(format!("does not match regular expression \"{PATTERN}\".")),
     }
 }
 
+fn expand_brace_group(group: &str) -> Result<Vec<String>, String> {
+    static RANGE_MATCHER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^([0-9]{1,5})\.\.([0-9]{1,5})$").unwrap());
+
+    if let Some(captures) = RANGE_MATCHER.captures(group) {
+        let (first_text, last_text) = (&captures[1], &captures[2]);
+        let first: usize = first_text.parse().unwrap();
+        let last: usize = last_text.parse().unwrap();
+        let zero_padded = [first_text, last_text]
+            .iter()
+            .any(|e| e.len() > 1 && e.starts_with('0'));
+        let width = if zero_padded {
+            first_text.len().max(last_text.len())
+        } else {
+            0
+        };
+        let numbers: Vec<usize> = if first <= last {
+            (first..=last).collect()
+        } else {
+            (last..=first).rev().collect()
+        };
+        return Ok(numbers
+            .into_iter()
+            .map(|number| format!("{number:0width$}"))
+            .collect());
+    }
+
+    if group.contains(',') {
+        return Ok(group.split(',').map(String::from).collect());
+    }
+
+    Err(format!(
+        "brace group \"{{{group}}}\" is neither a list like {{a,b}} nor a range like {{1..3}}."
+    ))
+}
+
+fn expand_service_syntax(text: &str) -> Result<Vec<String>, String> {
+    // Leave the syntax of services for other probes to these probes
+    if registered_scheme_of(text).is_some_and(|scheme| scheme != "tcp") {
+        return Ok(vec![text.to_string()]);
+    }
+
+    // Keep options like "?banner=ssh" out of expansion, e.g. for braces in regular expressions
+    let (text, options) = match text.split_once('?') {
+        Some((text, options)) => (text, format!("?{options}")),
+        None => (text, String::new()),
+    };
+
+    // Turn port range "host:8000-8004" into "host:{8000..8004}"
+    static PORT_RANGE_MATCHER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r":([0-9]{1,5})-([0-9]{1,5})$").unwrap());
+    let text = PORT_RANGE_MATCHER.replace(text, ":{$1..$2}");
+
+    let mut parts: Vec<Vec<String>> = Vec::new();
+    let mut rest: &str = &text;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(offset) => start + offset,
+            None => return Err(String::from("has an unclosed brace \"{\".")),
+        };
+        parts.push(vec![rest[..start].to_string()]);
+        parts.push(expand_brace_group(&rest[start + 1..end])?);
+        rest = &rest[end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(String::from("has an unopened brace \"}\"."));
+    }
+    parts.push(vec![rest.to_string()]);
+
+    let count = parts
+        .iter()
+        .try_fold(1usize, |count, part| count.checked_mul(part.len()))
+        .unwrap_or(usize::MAX);
+    if count > MAX_EXPANDED_SERVICES {
+        return Err(format!(
+            "expands to {count} services, more than the limit of {MAX_EXPANDED_SERVICES}."
+        ));
+    }
+
+    let mut services: Vec<String> = vec![String::new()];
+    for part in parts {
+        services = services
+            .iter()
+            .flat_map(|prefix| part.iter().map(move |suffix| format!("{prefix}{suffix}")))
+            .collect();
+    }
+
+    services
+        .iter()
+        .map(|service| parse_service_syntax(service).map(|service| service + &options))
+        .collect()
+}
+
 pub(crate) fn command() -> Command {
     command!()
         .arg(
@@ -29,6 +137,68 @@ pub(crate) fn command() -> Command {
                 .short('q')
                 .help("Do not output any status messages"),
         )
+        .arg(
+            Arg::new("verbosity")
+                .action(ArgAction::Count)
+                .long("verbose")
+                .short('v')
+                .conflicts_with("quiet")
+                .help("Output details of each attempt to stderr; pass twice for even more detail"),
+        )
+        .arg(
+            Arg::new("output_format")
+                .long("output")
+                .short('o')
+                .value_name("format")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .help("Format of status messages; \"json\" emits JSON Lines to stdout"),
+        )
+        .arg(
+            Arg::new("line_template")
+                .long("log-format")
+                .value_name("template")
+                .value_parser(parse_line_template)
+                .default_value(DEFAULT_LINE_TEMPLATE)
+                .help("Template for status lines; supports {timestamp}, {level}, {icon},\n{service}, {elapsed} and {message}"),
+        )
+        .arg(
+            Arg::new("timestamp_format")
+                .long("timestamps")
+                .value_name("format")
+                .value_parser(["rfc3339", "monotonic"])
+                .help("Prefix status messages with a timestamp, monotonic counts seconds since start"),
+        )
+        .arg(
+            Arg::new("log_target")
+                .long("log-target")
+                .value_name("target")
+                .value_parser(parse_log_target)
+                .default_value("console")
+                .help("Where to send status messages: \"console\" (stdout and stderr), \"stderr\",\n\"journald\" or \"syslog\""),
+        )
+        .arg(
+            Arg::new("junit_path")
+                .long("report-junit")
+                .value_name("path")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write a JUnit XML report with one testcase per service to <path>"),
+        )
+        .arg(
+            Arg::new("openmetrics_path")
+                .long("report-openmetrics")
+                .value_name("path")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write wait duration, attempts and success per service to <path>\nin OpenMetrics text format, e.g. for the textfile collector of node_exporter"),
+        )
+        .arg(
+            Arg::new("readiness_address")
+                .long("serve-readiness")
+                .value_name("address")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .conflicts_with_all(["strict", "junit_path", "openmetrics_path", "command"])
+                .help("Keep re-probing services and serve /healthz and /readyz on <address>,\ne.g. 0.0.0.0:8080, answering 200 only while all services are available;\n--timeout applies to each probe"),
+        )
         .arg(
             Arg::new("strict")
                 .action(ArgAction::SetTrue)
@@ -51,9 +221,9 @@ pub(crate) fn command() -> Command {
                 .long("service")
                 .short('s')
                 .value_name("host:port")
-                .value_parser(parse_service_syntax)
+                .value_parser(expand_service_syntax)
                 .num_args(0..)
-                .help("Service to test via the TCP protocol; can be passed multiple times"),
+                .help("Service to test via the TCP protocol, \"host:port\" or \"tcp://host:port\",\n\"?banner=<regex>\" (or \"ssh\", \"smtp\", ...) for what the server sends first;\n\"cmd:<shell command>[?timeout=<seconds>]\" waits for a command to exit with code 0,\n\"file:<path>\" and \"dir:<path>\" for a file or directory to exist,\n\"pid:<path>\" and \"process:<name>\" for a process to run, \"?exited\" to end,\n\"grpc://host:port/service\" for the gRPC health service to report serving,\n\"script://host:port?send=<bytes>&expect=<regex>\" (or \"script+unix:<path>?...\") for a dialogue,\n\"amqp://[user:password@]host:port[/vhost]\" for an AMQP 0-9-1 server like RabbitMQ,\n\"kafka://host:port[?topic=<name>]\" for a Kafka broker and optionally a topic with leaders,\n\"mongodb://host:port[?primary]\" for a MongoDB server and optionally a writable primary,\n\"memcached://host:port\" and \"zookeeper://host:port[?ruok|?mode=<mode>]\",\n\"elasticsearch://host:port[/index][?status=green]\" for a cluster health of yellow or better;\ncan be passed multiple times;\nexpands node-{1..3}:80, {a,b}:80 and port ranges like host:8000-8004"),
         )
         .arg(
             Arg::new("command")
@@ -64,9 +234,12 @@ pub(crate) fn command() -> Command {
 
 #[cfg(test)]
 mod tests {
-    use crate::network::TimeoutSeconds;
+    use crate::exec::register_command_probe;
+
+    use super::TimeoutSeconds;
 
     use super::command;
+    use super::expand_service_syntax;
     use super::parse_service_syntax;
 
     #[test]
@@ -80,23 +253,130 @@ mod tests {
             Ok(String::from("127.0.0.1:631"))
         );
         assert_eq!(parse_service_syntax("h:1"), Ok(String::from("h:1")));
+        assert_eq!(
+            parse_service_syntax("tcp://h:1"),
+            Ok(String::from("tcp://h:1"))
+        );
+        assert_eq!(
+            parse_service_syntax("[fe80::1%eth0]:8080"),
+            Ok(String::from("[fe80::1%eth0]:8080"))
+        );
+        assert_eq!(
+            parse_service_syntax("[fe80::1%2]:8080"),
+            Ok(String::from("[fe80::1%2]:8080"))
+        );
     }
 
     #[test]
     fn test_parse_service_syntax_for_invalid() {
         let expected_error = Err(String::from(
-            "does not match regular expression \"^(\\[[0-9a-fA-F.:]+\\]|[^:]+):([1-9][0-9]{0,4})$\".",
+            "does not match regular expression \"^(\\[[0-9a-fA-F.:]+(%[0-9a-zA-Z_.-]+)?\\]|[^:]+):([1-9][0-9]{0,4})$\".",
         ));
         assert_eq!(parse_service_syntax("h:123456"), expected_error);
         assert_eq!(parse_service_syntax("no colon"), expected_error);
         assert_eq!(parse_service_syntax(":123"), expected_error);
+        assert_eq!(parse_service_syntax("[::1%]:123"), expected_error);
+        assert_eq!(parse_service_syntax("[::1%a b]:123"), expected_error);
+    }
+
+    #[test]
+    fn test_expand_service_syntax_for_valid() {
+        assert_eq!(expand_service_syntax("h:1"), Ok(vec![String::from("h:1")]));
+        assert_eq!(
+            expand_service_syntax("node-{1..3}:9092"),
+            Ok(vec![
+                String::from("node-1:9092"),
+                String::from("node-2:9092"),
+                String::from("node-3:9092"),
+            ])
+        );
+        assert_eq!(
+            expand_service_syntax("node-{09..10}:80"),
+            Ok(vec![String::from("node-09:80"), String::from("node-10:80")])
+        );
+        assert_eq!(
+            expand_service_syntax("h:{3..2}"),
+            Ok(vec![String::from("h:3"), String::from("h:2")])
+        );
+        assert_eq!(
+            expand_service_syntax("127.0.0.1:8000-8002"),
+            Ok(vec![
+                String::from("127.0.0.1:8000"),
+                String::from("127.0.0.1:8001"),
+                String::from("127.0.0.1:8002"),
+            ])
+        );
+        assert_eq!(
+            expand_service_syntax("{db,[::1]}:{1,2}"),
+            Ok(vec![
+                String::from("db:1"),
+                String::from("db:2"),
+                String::from("[::1]:1"),
+                String::from("[::1]:2"),
+            ])
+        );
+        assert_eq!(
+            expand_service_syntax("tcp://h:{1,2}"),
+            Ok(vec![String::from("tcp://h:1"), String::from("tcp://h:2")])
+        );
+        assert_eq!(
+            expand_service_syntax("h:{1,2}?banner=^a{2}"),
+            Ok(vec![
+                String::from("h:1?banner=^a{2}"),
+                String::from("h:2?banner=^a{2}"),
+            ])
+        );
+        register_command_probe();
+        assert_eq!(
+            expand_service_syntax("cmd:test -f /x{1,2}"),
+            Ok(vec![String::from("cmd:test -f /x{1,2}")])
+        );
+        assert_eq!(
+            expand_service_syntax("h:1-256").map(|services| services.len()),
+            Ok(256)
+        );
+    }
+
+    #[test]
+    fn test_expand_service_syntax_for_invalid() {
+        assert_eq!(
+            expand_service_syntax("h:1-257"),
+            Err(String::from(
+                "expands to 257 services, more than the limit of 256."
+            ))
+        );
+        assert_eq!(
+            expand_service_syntax("h{1..9999}:{1..99}"),
+            Err(String::from(
+                "expands to 989901 services, more than the limit of 256."
+            ))
+        );
+        assert_eq!(
+            expand_service_syntax("h{1..2:1"),
+            Err(String::from("has an unclosed brace \"{\"."))
+        );
+        assert_eq!(
+            expand_service_syntax("h}:1"),
+            Err(String::from("has an unopened brace \"}\"."))
+        );
+        assert_eq!(
+            expand_service_syntax("h{x}:1"),
+            Err(String::from(
+                "brace group \"{x}\" is neither a list like {a,b} nor a range like {1..3}."
+            ))
+        );
+        assert!(expand_service_syntax("h:0-1").is_err());
+        assert!(expand_service_syntax("{a,b}").is_err());
+        assert!(expand_service_syntax("h?banner=ssh").is_err());
     }
 
     #[test]
     fn test_command_for_defaults() {
         let matches = command().get_matches_from(["rust-for-it"]);
         assert_eq!(*matches.get_one::<bool>("quiet").unwrap(), false);
+        assert_eq!(matches.get_count("verbosity"), 0);
         assert_eq!(*matches.get_one::<bool>("strict").unwrap(), false);
+        assert_eq!(matches.get_one::<String>("output_format").unwrap(), "text");
         assert_eq!(
             *matches
                 .get_one::<TimeoutSeconds>("timeout_seconds")
@@ -104,7 +384,7 @@ mod tests {
             15
         );
         assert!(matches
-            .get_many::<String>("services")
+            .get_many::<Vec<String>>("services")
             .unwrap_or_default()
             .next()
             .is_none());
@@ -120,6 +400,8 @@ mod tests {
         let matches = command().get_matches_from([
             "rust-for-it",
             "--quiet",
+            "--output",
+            "json",
             "--strict",
             "--timeout",
             "123",
@@ -135,6 +417,7 @@ mod tests {
         ]);
 
         assert_eq!(*matches.get_one::<bool>("quiet").unwrap(), true);
+        assert_eq!(matches.get_one::<String>("output_format").unwrap(), "json");
         assert_eq!(*matches.get_one::<bool>("strict").unwrap(), true);
         assert_eq!(
             *matches
@@ -144,8 +427,9 @@ mod tests {
         );
 
         let actual_services: Vec<_> = matches
-            .get_many::<String>("services")
+            .get_many::<Vec<String>>("services")
             .unwrap_or_default()
+            .flatten()
             .map(|e| e.as_str())
             .collect();
         assert_eq!(actual_services, ["one:1", "two:2"]);
@@ -158,6 +442,19 @@ mod tests {
         assert_eq!(actual_command, ["echo", "hello", "--", "world"]);
     }
 
+    #[test]
+    fn test_command_for_verbosity() {
+        let matches = command().get_matches_from(["rust-for-it", "-vv"]);
+        assert_eq!(matches.get_count("verbosity"), 2);
+
+        let matches = command().get_matches_from(["rust-for-it", "--verbose"]);
+        assert_eq!(matches.get_count("verbosity"), 1);
+
+        assert!(command()
+            .try_get_matches_from(["rust-for-it", "-q", "-v"])
+            .is_err());
+    }
+
     #[test]
     fn test_command_for_non_defaults_short() {
         let matches = command().get_matches_from([
@@ -180,8 +477,9 @@ mod tests {
         );
 
         let actual_services: Vec<_> = matches
-            .get_many::<String>("services")
+            .get_many::<Vec<String>>("services")
             .unwrap_or_default()
+            .flatten()
             .map(|e| e.as_str())
             .collect();
         assert_eq!(actual_services, ["one:1", "two:2"]);
@@ -0,0 +1,329 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use log::Record;
+use rust_for_it::display_form_of;
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+const MAX_ERROR_WIDTH: usize = 60;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum RowState {
+    Pending,
+    Waiting,
+    Available,
+    TimedOut,
+    Failed,
+}
+
+impl RowState {
+    fn label(&self) -> &'static str {
+        match self {
+            RowState::Pending => "pending",
+            RowState::Waiting => "waiting",
+            RowState::Available => "available",
+            RowState::TimedOut => "timed out",
+            RowState::Failed => "failed",
+        }
+    }
+
+    fn ansi_color(&self) -> &'static str {
+        match self {
+            RowState::Pending => "",
+            RowState::Waiting => "\x1b[33m",
+            RowState::Available => "\x1b[32m",
+            RowState::TimedOut | RowState::Failed => "\x1b[31m",
+        }
+    }
+}
+
+struct Row {
+    service: String,
+    state: RowState,
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+    attempts: u64,
+    last_error: String,
+    warning: Option<String>,
+}
+
+impl Row {
+    /// Label and color of the state column, warnings taking the place of "available"
+    fn state_cell(&self) -> (&'static str, &'static str) {
+        match (self.state, &self.warning) {
+            (RowState::Available, Some(_)) => ("warning", "\x1b[33m"),
+            (state, _) => (state.label(), state.ansi_color()),
+        }
+    }
+
+    /// Text of the last column, any warning taking precedence over the last error
+    fn message(&self) -> &str {
+        self.warning.as_deref().unwrap_or(&self.last_error)
+    }
+}
+
+/// Live-updating table with one row per service, re-drawn in place
+/// using ANSI escape sequences
+pub(crate) struct Dashboard {
+    rows: Vec<Row>,
+    lines_drawn: usize,
+}
+
+impl Dashboard {
+    pub(crate) fn new<S: AsRef<str>>(services: &[S]) -> Self {
+        let mut rows: Vec<Row> = Vec::new();
+        for service in services {
+            let service = display_form_of(service.as_ref());
+            if rows.iter().any(|row| row.service == service) {
+                continue;
+            }
+            rows.push(Row {
+                service,
+                state: RowState::Pending,
+                started: None,
+                elapsed: None,
+                attempts: 0,
+                last_error: String::new(),
+                warning: None,
+            });
+        }
+        Dashboard {
+            rows,
+            lines_drawn: 0,
+        }
+    }
+
+    /// Applies a status record to the table,
+    /// returns `false` for records that are not about a known service
+    pub(crate) fn update(&mut self, record: &Record) -> bool {
+        let key_values = record.key_values();
+        let text_of = |key: &str| key_values.get(key.into()).map(|value| value.to_string());
+
+        let (Some(service), Some(event)) = (text_of("service"), text_of("event")) else {
+            return false;
+        };
+        let Some(row) = self.rows.iter_mut().find(|row| row.service == service) else {
+            return false;
+        };
+
+        if let Some(attempts) = key_values
+            .get("attempt".into())
+            .and_then(|value| value.to_u64())
+        {
+            row.attempts = attempts;
+        }
+        if let Some(error) = text_of("error") {
+            row.last_error = error;
+        }
+        if let Some(elapsed) = key_values
+            .get("elapsed".into())
+            .and_then(|value| value.to_f64())
+        {
+            row.elapsed = Some(Duration::from_secs_f64(elapsed.max(0.0)));
+        }
+
+        match event.as_str() {
+            "waiting" => {
+                row.state = RowState::Waiting;
+                row.started = Some(Instant::now());
+            }
+            "available" => row.state = RowState::Available,
+            "timed_out" => row.state = RowState::TimedOut,
+            "failed" => row.state = RowState::Failed,
+            "warning" => row.warning = Some(record.args().to_string()),
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Makes the next call to `render` draw a fresh table
+    /// rather than overwrite the previous one
+    pub(crate) fn detach(&mut self) {
+        self.lines_drawn = 0;
+    }
+
+    pub(crate) fn render(&mut self, now: Instant) -> String {
+        let service_width = self
+            .rows
+            .iter()
+            .map(|row| row.service.chars().count())
+            .max()
+            .unwrap_or_default()
+            .max("SERVICE".len());
+
+        let mut text = String::new();
+        if self.lines_drawn > 0 {
+            let _ = write!(text, "\x1b[{}A", self.lines_drawn);
+        }
+
+        let _ = writeln!(
+            text,
+            "\r\x1b[2K{:service_width$}  {:9}  {:>8}  {:>8}  LAST ERROR",
+            "SERVICE", "STATE", "ELAPSED", "ATTEMPTS"
+        );
+        for row in &self.rows {
+            let elapsed = match (row.state, row.elapsed, row.started) {
+                (RowState::Available | RowState::TimedOut | RowState::Failed, Some(elapsed), _) => {
+                    elapsed
+                }
+                (_, _, Some(started)) => now.saturating_duration_since(started),
+                _ => Duration::ZERO,
+            };
+            let (label, ansi_color) = row.state_cell();
+            let message: String = row.message().chars().take(MAX_ERROR_WIDTH).collect();
+            let _ = writeln!(
+                text,
+                "\r\x1b[2K{:service_width$}  {}{:9}\x1b[0m  {:>7.1}s  {:>8}  {}",
+                row.service,
+                ansi_color,
+                label,
+                elapsed.as_secs_f32(),
+                row.attempts,
+                message
+            );
+        }
+
+        self.lines_drawn = self.rows.len() + 1;
+        text
+    }
+
+    /// Lines with the full messages that the table had to cut short,
+    /// for printing below the table once it is no longer updated
+    pub(crate) fn render_truncated_messages(&self) -> String {
+        let mut text = String::new();
+        for row in &self.rows {
+            let message = row.message();
+            if message.chars().count() > MAX_ERROR_WIDTH {
+                let _ = writeln!(text, "{}: {message}", row.service);
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+
+    use std::time::{Duration, Instant};
+
+    use super::Dashboard;
+    use super::RowState;
+
+    fn update_with(dashboard: &mut Dashboard, key_values: &[(&str, &str)]) -> bool {
+        dashboard.update(
+            &Record::builder()
+                .level(Level::Info)
+                .key_values(&key_values)
+                .build(),
+        )
+    }
+
+    #[test]
+    fn test_update() {
+        let mut dashboard = Dashboard::new(&["a:1", "b:2", "a:1"]);
+        assert_eq!(dashboard.rows.len(), 2);
+        assert_eq!(dashboard.rows[0].state, RowState::Pending);
+
+        assert!(!update_with(&mut dashboard, &[("event", "waiting")]));
+        assert!(!update_with(
+            &mut dashboard,
+            &[("event", "waiting"), ("service", "c:3")]
+        ));
+
+        assert!(update_with(
+            &mut dashboard,
+            &[("event", "waiting"), ("service", "a:1")]
+        ));
+        assert_eq!(dashboard.rows[0].state, RowState::Waiting);
+        assert!(dashboard.rows[0].started.is_some());
+
+        dashboard.update(
+            &Record::builder()
+                .level(Level::Debug)
+                .key_values(&[
+                    ("event", log::kv::Value::from("attempt_failed")),
+                    ("service", log::kv::Value::from("a:1")),
+                    ("attempt", log::kv::Value::from(3u64)),
+                    ("error", log::kv::Value::from("Connection refused")),
+                ])
+                .build(),
+        );
+        assert_eq!(dashboard.rows[0].state, RowState::Waiting);
+        assert_eq!(dashboard.rows[0].attempts, 3);
+        assert_eq!(dashboard.rows[0].last_error, "Connection refused");
+
+        dashboard.update(
+            &Record::builder()
+                .level(Level::Info)
+                .key_values(&[
+                    ("event", log::kv::Value::from("available")),
+                    ("service", log::kv::Value::from("a:1")),
+                    ("attempt", log::kv::Value::from(4u64)),
+                    ("elapsed", log::kv::Value::from(1.5f64)),
+                ])
+                .build(),
+        );
+        assert_eq!(dashboard.rows[0].state, RowState::Available);
+        assert_eq!(dashboard.rows[0].attempts, 4);
+        assert_eq!(dashboard.rows[0].elapsed, Some(Duration::from_millis(1500)));
+
+        assert!(dashboard.update(
+            &Record::builder()
+                .level(Level::Warn)
+                .args(format_args!("Careful."))
+                .key_values(&[("event", "warning"), ("service", "b:2")])
+                .build(),
+        ));
+        assert_eq!(dashboard.rows[1].warning.as_deref(), Some("Careful."));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut dashboard = Dashboard::new(&["a:1", "long-name:2"]);
+        update_with(&mut dashboard, &[("event", "waiting"), ("service", "a:1")]);
+        update_with(
+            &mut dashboard,
+            &[("event", "timed_out"), ("service", "long-name:2")],
+        );
+        dashboard.rows[1].elapsed = Some(Duration::from_secs(2));
+        dashboard.rows[1].last_error = String::from("x").repeat(100);
+        let now = dashboard.rows[0].started.unwrap() + Duration::from_millis(1200);
+
+        let expected_table = format!(
+            "\r\x1b[2KSERVICE      STATE       ELAPSED  ATTEMPTS  LAST ERROR\n\
+             \r\x1b[2Ka:1          \x1b[33mwaiting  \x1b[0m      1.2s         0  \n\
+             \r\x1b[2Klong-name:2  \x1b[31mtimed out\x1b[0m      2.0s         0  {}\n",
+            "x".repeat(60)
+        );
+        assert_eq!(dashboard.render(now), expected_table);
+        assert_eq!(dashboard.render(now), format!("\x1b[3A{expected_table}"));
+
+        dashboard.detach();
+        assert_eq!(dashboard.render(now), expected_table);
+
+        assert_eq!(
+            dashboard.render_truncated_messages(),
+            format!("long-name:2: {}\n", "x".repeat(100))
+        );
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let mut dashboard = Dashboard::new(&["a:1"]);
+        dashboard.rows[0].state = RowState::Available;
+        dashboard.rows[0].elapsed = Some(Duration::from_secs(1));
+        dashboard.rows[0].last_error = String::from("Connection refused");
+        dashboard.rows[0].warning = Some(String::from("Certificate expires soon"));
+
+        assert!(dashboard
+            .render(Instant::now())
+            .ends_with("\x1b[33mwarning  \x1b[0m      1.0s         0  Certificate expires soon\n"));
+        assert_eq!(dashboard.render_truncated_messages(), "");
+    }
+}
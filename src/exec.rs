@@ -4,12 +4,35 @@
 // SPDX-License-Identifier: MIT
 
 use log::error;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rust_for_it::{register_probe_scheme, Probe, ProbeOutcome};
+#[cfg(unix)]
+use subprocess::unix::PopenExt;
 use subprocess::Exec;
 use subprocess::ExitStatus;
+#[cfg(not(unix))]
+use subprocess::NullFile;
+use subprocess::Popen;
+#[cfg(unix)]
+use subprocess::PopenConfig;
 use subprocess::PopenError;
+use subprocess::Redirection;
 use subprocess::Result as PopenResult;
 
-use std::io::ErrorKind;
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Read};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::Duration;
+
+/// Upper bound for the output of a probe command kept for error messages
+const MAX_CAPTURED_OUTPUT: usize = 4096;
+
+/// Time to wait for the rest of the output once the command has ended
+const OUTPUT_GRACE_PERIOD: Duration = Duration::from_millis(200);
 
 fn exit_code_from(exit_status: ExitStatus) -> i32 {
     match exit_status {
@@ -42,16 +65,213 @@ pub(crate) fn run_command(command: &str, args: Vec<&str>) -> i32 {
     process_popen_result(popen_result, command)
 }
 
+/// Probe running a shell command until it exits with code 0,
+/// for services like "cmd:pg_isready -h db" or "cmd:./check.sh?timeout=5"
+struct CommandProbe {
+    command: String,
+    timeout: Option<Duration>,
+}
+
+impl CommandProbe {
+    fn create(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let command = service
+            .split_once(':')
+            .map_or("", |(_, command)| command.trim());
+        let (command, timeout) = split_timeout_option(command)?;
+        if command.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "No command given"));
+        }
+        Ok(Box::new(CommandProbe {
+            command: command.to_string(),
+            timeout,
+        }))
+    }
+}
+
+/// Splits off a trailing "?timeout=<seconds>" limiting each run of the command
+fn split_timeout_option(command: &str) -> Result<(&str, Option<Duration>), io::Error> {
+    static TIMEOUT_MATCHER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\?timeout=([0-9]+)$").unwrap());
+    let Some(captures) = TIMEOUT_MATCHER.captures(command) else {
+        return Ok((command, None));
+    };
+    let seconds = captures[1]
+        .parse::<u64>()
+        .ok()
+        .filter(|&seconds| seconds > 0)
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                "Timeout needs to be a positive number of seconds",
+            )
+        })?;
+    let command = command[..captures.get(0).unwrap().start()].trim_end();
+    Ok((command, Some(Duration::from_secs(seconds))))
+}
+
+/// Starts the command in a process group of its own
+/// so that killing the group also ends any processes started by the shell
+#[cfg(unix)]
+fn start_shell(command: &str) -> PopenResult<Popen> {
+    Popen::create(
+        &["sh", "-c", command],
+        PopenConfig {
+            stdin: Redirection::File(File::open("/dev/null")?),
+            stdout: Redirection::Pipe,
+            stderr: Redirection::Merge,
+            setpgid: true,
+            ..Default::default()
+        },
+    )
+}
+
+#[cfg(not(unix))]
+fn start_shell(command: &str) -> PopenResult<Popen> {
+    Exec::shell(command)
+        .stdin(NullFile)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .popen()
+}
+
+fn kill_shell(process: &mut Popen) {
+    #[cfg(unix)]
+    let _ = process.send_signal_group(libc::SIGKILL);
+    let _ = process.kill();
+    let _ = process.wait();
+}
+
+/// Turns output into a single line without control characters or ANSI escape sequences
+/// so that it is safe to put into log lines and reports
+fn single_line_of(output: &[u8]) -> String {
+    static ESCAPE_SEQUENCE_MATCHER: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)?|[@-_])").unwrap()
+    });
+    let output = String::from_utf8_lossy(output);
+    let output = ESCAPE_SEQUENCE_MATCHER.replace_all(&output, "");
+    output
+        .replace(|c: char| c.is_control(), " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Error for a failed attempt, including any output of the command
+fn command_error_of(kind: ErrorKind, text: &str, output: &[u8]) -> io::Error {
+    let output = single_line_of(output);
+    if output.is_empty() {
+        io::Error::new(kind, text)
+    } else {
+        io::Error::new(kind, format!("{text}, output: {output}"))
+    }
+}
+
+/// Reads output until the end, keeping no more than the first few bytes
+/// so that a chatty command never blocks on a full pipe
+fn drain_output(mut output: File, captured: &Mutex<Vec<u8>>) {
+    let mut buffer = [0u8; 4096];
+    loop {
+        match output.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => {
+                let mut captured = captured.lock().expect("poisoned lock");
+                let length = length.min(MAX_CAPTURED_OUTPUT - captured.len());
+                captured.extend_from_slice(&buffer[..length]);
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+}
+
+impl Probe for CommandProbe {
+    fn activity(&self) -> String {
+        format!("run '{}'", self.command)
+    }
+
+    fn attempt(&self, timeout: Option<Duration>) -> ProbeOutcome {
+        let timeout = match (timeout, self.timeout) {
+            (Some(timeout), Some(own_timeout)) => Some(timeout.min(own_timeout)),
+            (timeout, own_timeout) => timeout.or(own_timeout),
+        };
+        let popen_result = start_shell(&self.command);
+        let mut process = match popen_result {
+            Ok(process) => process,
+            Err(PopenError::IoError(error))
+                if matches!(
+                    error.kind(),
+                    ErrorKind::NotFound | ErrorKind::PermissionDenied
+                ) =>
+            {
+                return ProbeOutcome::Fatal(error)
+            }
+            Err(error) => return ProbeOutcome::NotYet(io::Error::other(error)),
+        };
+
+        let stdout = process.stdout.take().expect("stdout is piped");
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let (drained_sender, drained_receiver) = channel();
+        {
+            let captured = captured.clone();
+            spawn(move || {
+                drain_output(stdout, &captured);
+                let _ = drained_sender.send(());
+            });
+        }
+
+        let wait_result = match timeout {
+            None => process.wait().map(Some),
+            Some(timeout) => process.wait_timeout(timeout),
+        }
+        .map_err(io::Error::other);
+        if !matches!(wait_result, Ok(Some(_))) {
+            kill_shell(&mut process);
+        }
+
+        // NOTE: Processes left running in the background may keep the pipe open
+        //       past the exit of the shell, so this only waits a little longer
+        let _ = drained_receiver.recv_timeout(OUTPUT_GRACE_PERIOD);
+        let output = captured.lock().expect("poisoned lock").clone();
+
+        match wait_result {
+            Ok(Some(ExitStatus::Exited(0))) => ProbeOutcome::Ready,
+            Ok(Some(exit_status)) => {
+                let text = format!("exit code {}", exit_code_from(exit_status));
+                ProbeOutcome::NotYet(command_error_of(ErrorKind::Other, &text, &output))
+            }
+            Ok(None) => {
+                let text = "Command timed out";
+                ProbeOutcome::NotYet(command_error_of(ErrorKind::TimedOut, text, &output))
+            }
+            Err(error) => ProbeOutcome::NotYet(error),
+        }
+    }
+
+    fn reports_last_error(&self) -> bool {
+        true
+    }
+}
+
+/// Makes "cmd:..." services available, needed before parsing the command line
+pub(crate) fn register_command_probe() {
+    register_probe_scheme("cmd", CommandProbe::create);
+}
+
 #[cfg(test)]
 mod tests {
     use subprocess::ExitStatus;
     use subprocess::PopenError;
 
     use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    use rust_for_it::{ProbeOutcome, Waiter};
 
     use super::exit_code_from;
     use super::process_popen_result;
+    use super::register_command_probe;
     use super::run_command;
+    use super::single_line_of;
+    use super::CommandProbe;
 
     #[test]
     fn test_exit_code_from() {
@@ -97,4 +317,132 @@ mod tests {
     fn test_run_command_for_bad() {
         assert_eq!(run_command("sh", vec!["-c", "exit 123"]), 123);
     }
+
+    #[test]
+    fn test_command_probe_create() {
+        let probe = CommandProbe::create("cmd: test -f /ready").unwrap();
+        assert_eq!(probe.activity(), "run 'test -f /ready'");
+        assert_eq!(
+            CommandProbe::create("cmd: ").err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_command_probe_create_with_timeout() {
+        let probe = CommandProbe::create("cmd:./check.sh --all ?timeout=5").unwrap();
+        assert_eq!(probe.activity(), "run './check.sh --all'");
+        let probe = CommandProbe::create("cmd:ls /tmp/?timeout").unwrap();
+        assert_eq!(probe.activity(), "run 'ls /tmp/?timeout'");
+        assert_eq!(
+            CommandProbe::create("cmd:true?timeout=0")
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            CommandProbe::create("cmd:?timeout=5").err().unwrap().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_single_line_of() {
+        assert_eq!(single_line_of(b"  one\ntwo\r\n\tthree  "), "one two three");
+        assert_eq!(
+            single_line_of(b"\x1b[1;31mred\x1b[0m \x1b]0;title\x07bell\x07 \x00nul"),
+            "red bell nul"
+        );
+    }
+
+    #[test]
+    fn test_command_probe_attempt_for_good() {
+        let probe = CommandProbe::create("cmd:exit 0").unwrap();
+        assert!(matches!(probe.attempt(None), ProbeOutcome::Ready));
+        assert!(matches!(
+            probe.attempt(Some(Duration::from_secs(5))),
+            ProbeOutcome::Ready
+        ));
+    }
+
+    #[test]
+    fn test_command_probe_attempt_for_bad() {
+        let probe = CommandProbe::create("cmd:echo not ready; exit 3").unwrap();
+        match probe.attempt(Some(Duration::from_secs(5))) {
+            ProbeOutcome::NotYet(error) => {
+                assert_eq!(error.to_string(), "exit code 3, output: not ready")
+            }
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn test_command_probe_attempt_for_chatty() {
+        let probe =
+            CommandProbe::create("cmd:head -c 100000 /dev/zero | tr '\\0' x; exit 3").unwrap();
+        match probe.attempt(Some(Duration::from_secs(5))) {
+            ProbeOutcome::NotYet(error) => {
+                let expected = format!("exit code 3, output: {}", "x".repeat(4096));
+                assert_eq!(error.to_string(), expected);
+            }
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn test_command_probe_reports_last_error() {
+        register_command_probe();
+        let report = Waiter::new()
+            .service("cmd:echo not ready; exit 3")
+            .timeout(Duration::from_millis(300))
+            .retry_delay(Duration::from_millis(100))
+            .wait();
+        assert_eq!(
+            report.services[0].failure_message.as_deref(),
+            Some(
+                "cmd:echo not ready; exit 3 timed out after waiting for 0.3 seconds \
+                 (exit code 3, output: not ready)."
+            )
+        );
+    }
+
+    #[test]
+    fn test_command_probe_attempt_for_slow() {
+        let probe = CommandProbe::create("cmd:echo started; sleep 5").unwrap();
+        match probe.attempt(Some(Duration::from_millis(300))) {
+            ProbeOutcome::NotYet(error) => {
+                assert_eq!(error.kind(), ErrorKind::TimedOut);
+                assert_eq!(error.to_string(), "Command timed out, output: started");
+            }
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn test_command_probe_attempt_with_own_timeout() {
+        let probe = CommandProbe::create("cmd:sleep 5?timeout=1").unwrap();
+        let timer = Instant::now();
+        match probe.attempt(None) {
+            ProbeOutcome::NotYet(error) => assert_eq!(error.kind(), ErrorKind::TimedOut),
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+        assert!(timer.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_command_probe_attempt_kills_background_processes() {
+        let marker = std::env::temp_dir().join(format!("rust-for-it-{}", std::process::id()));
+        let command = format!("cmd:(sleep 1; touch {}) & sleep 5", marker.display());
+        let probe = CommandProbe::create(&command).unwrap();
+        let timer = Instant::now();
+        assert!(matches!(
+            probe.attempt(Some(Duration::from_millis(300))),
+            ProbeOutcome::NotYet(_)
+        ));
+        assert!(timer.elapsed() < Duration::from_secs(1));
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+    }
 }
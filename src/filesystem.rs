@@ -0,0 +1,450 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use regex::Regex;
+
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::probe::{Probe, ProbeOutcome};
+use crate::util::invalid_input;
+
+/// Longest time that a single attempt waits for changes to the file system,
+/// so that cancellation is noticed in time even without a timeout
+const MAX_WATCH_DURATION: Duration = Duration::from_secs(5);
+
+thread_local! {
+    /// Time that option "newer" refers to, the creation of the probe unless set
+    static REFERENCE_TIME: Cell<Option<SystemTime>> = const { Cell::new(None) };
+}
+
+/// Runs the given function with option "newer" referring to the given time
+/// rather than to the creation of each probe, e.g. for probes created over and over
+pub(crate) fn with_reference_time<F, R>(reference_time: SystemTime, inner_function: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous = REFERENCE_TIME.replace(Some(reference_time));
+    let ret = inner_function();
+    REFERENCE_TIME.set(previous);
+    ret
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum EntryKind {
+    File,
+    Directory,
+}
+
+/// Checks that a file or directory exists, for services like "file:/run/app/ready"
+/// or "dir:/srv/certs?non-empty"
+///
+/// Options go after a question mark and are separated by ampersands:
+/// "non-empty", "newer" (modified after waiting started) and "matches=<regex>"
+/// (for a file's content or the name of any directory entry) which has to come last.
+pub(crate) struct FileProbe {
+    kind: EntryKind,
+    path: PathBuf,
+    non_empty: bool,
+    newer_than: Option<SystemTime>,
+    matcher: Option<Regex>,
+}
+
+fn not_yet(text: String) -> ProbeOutcome {
+    ProbeOutcome::NotYet(io::Error::new(io::ErrorKind::NotFound, text))
+}
+
+impl FileProbe {
+    fn create_for(kind: EntryKind, service: &str) -> Result<FileProbe, io::Error> {
+        let spec = service.split_once(':').map_or("", |(_, spec)| spec);
+        let (path, mut options) = match spec.split_once('?') {
+            Some((path, options)) => (path, options),
+            None => (spec, ""),
+        };
+        if path.is_empty() {
+            return Err(invalid_input(String::from("No path given")));
+        }
+
+        let mut probe = FileProbe {
+            kind,
+            path: PathBuf::from(path),
+            non_empty: false,
+            newer_than: None,
+            matcher: None,
+        };
+        while !options.is_empty() {
+            if let Some(pattern) = options.strip_prefix("matches=") {
+                let matcher = Regex::new(pattern).map_err(|error| {
+                    invalid_input(format!("Invalid regular expression: {error}"))
+                })?;
+                probe.matcher = Some(matcher);
+                break;
+            }
+            let (option, rest) = options.split_once('&').unwrap_or((options, ""));
+            match option {
+                "non-empty" => probe.non_empty = true,
+                "newer" => {
+                    let reference_time = REFERENCE_TIME.get();
+                    probe.newer_than = Some(reference_time.unwrap_or_else(SystemTime::now));
+                }
+                _ => return Err(invalid_input(format!("Unknown option \"{option}\""))),
+            }
+            options = rest;
+        }
+        Ok(probe)
+    }
+
+    pub(crate) fn create_file(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        Ok(Box::new(Self::create_for(EntryKind::File, service)?))
+    }
+
+    pub(crate) fn create_directory(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        Ok(Box::new(Self::create_for(EntryKind::Directory, service)?))
+    }
+
+    fn check(&self) -> ProbeOutcome {
+        let path = self.path.display();
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(error) => return ProbeOutcome::NotYet(error),
+        };
+        match self.kind {
+            EntryKind::File if !metadata.is_file() => {
+                return not_yet(format!("{path} is not a file"))
+            }
+            EntryKind::Directory if !metadata.is_dir() => {
+                return not_yet(format!("{path} is not a directory"))
+            }
+            _ => {}
+        }
+
+        if let Some(newer_than) = self.newer_than {
+            match metadata.modified() {
+                Ok(modified) if modified >= newer_than => {}
+                Ok(_) => return not_yet(format!("{path} has not been modified yet")),
+                Err(error) => return ProbeOutcome::Fatal(error),
+            }
+        }
+
+        match self.kind {
+            EntryKind::File => {
+                if self.non_empty && metadata.len() == 0 {
+                    return not_yet(format!("{path} is empty"));
+                }
+                if let Some(matcher) = self.matcher.as_ref() {
+                    let content = match fs::read(&self.path) {
+                        Ok(content) => content,
+                        Err(error) => return ProbeOutcome::NotYet(error),
+                    };
+                    if !matcher.is_match(&String::from_utf8_lossy(&content)) {
+                        return not_yet(format!("{path} does not match \"{matcher}\""));
+                    }
+                }
+            }
+            EntryKind::Directory => {
+                let names: Vec<String> = match fs::read_dir(&self.path) {
+                    Ok(entries) => entries
+                        .filter_map(Result::ok)
+                        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                        .collect(),
+                    Err(error) => return ProbeOutcome::NotYet(error),
+                };
+                if self.non_empty && names.is_empty() {
+                    return not_yet(format!("{path} is empty"));
+                }
+                if let Some(matcher) = self.matcher.as_ref() {
+                    if !names.iter().any(|name| matcher.is_match(name)) {
+                        return not_yet(format!("{path} has no entry matching \"{matcher}\""));
+                    }
+                }
+            }
+        }
+
+        ProbeOutcome::Ready
+    }
+}
+
+impl Probe for FileProbe {
+    fn activity(&self) -> String {
+        match self.kind {
+            EntryKind::File => format!("find file {}", self.path.display()),
+            EntryKind::Directory => format!("find directory {}", self.path.display()),
+        }
+    }
+
+    fn attempt(&self, timeout: Option<Duration>) -> ProbeOutcome {
+        // NOTE: Watching starts before checking so that no change is missed in between
+        let watch = Watch::new(&self.path);
+        match self.check() {
+            ProbeOutcome::NotYet(error) => {
+                let Some(watch) = watch else {
+                    return ProbeOutcome::NotYet(error);
+                };
+                let duration = timeout.map_or(MAX_WATCH_DURATION, |timeout| {
+                    timeout.min(MAX_WATCH_DURATION)
+                });
+                if watch.wait(duration) {
+                    self.check()
+                } else {
+                    ProbeOutcome::NotYet(error)
+                }
+            }
+            outcome => outcome,
+        }
+    }
+}
+
+/// Nearest ancestor of a path that exists, e.g. "/run" for "/run/app/ready"
+/// if "/run/app" does not exist yet
+fn existing_ancestor_of(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .skip(1)
+        .map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                ancestor
+            }
+        })
+        .find(|ancestor| ancestor.is_dir())
+}
+
+/// Changes to a path and its nearest existing ancestor, via inotify
+#[cfg(any(target_os = "linux", target_os = "android"))]
+struct Watch {
+    fd: libc::c_int,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Watch {
+    /// `None` if inotify is not available, e.g. for running out of watches,
+    /// to fall back to polling
+    fn new(path: &Path) -> Option<Watch> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return None;
+        }
+        let watch = Watch { fd };
+
+        let mask = libc::IN_CREATE
+            | libc::IN_MOVED_TO
+            | libc::IN_MODIFY
+            | libc::IN_CLOSE_WRITE
+            | libc::IN_ATTRIB
+            | libc::IN_DELETE_SELF
+            | libc::IN_MOVE_SELF;
+        let mut watched_any = false;
+        for watched_path in existing_ancestor_of(path).into_iter().chain([path]) {
+            let Ok(c_path) = CString::new(watched_path.as_os_str().as_bytes()) else {
+                continue;
+            };
+            if unsafe { libc::inotify_add_watch(fd, c_path.as_ptr(), mask) } >= 0 {
+                watched_any = true;
+            }
+        }
+        watched_any.then_some(watch)
+    }
+
+    /// Returns whether anything changed within the given time
+    fn wait(&self, duration: Duration) -> bool {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_millis = duration.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let ready_count = unsafe { libc::poll(&mut poll_fd, 1, timeout_millis) };
+        ready_count > 0
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Drop for Watch {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Placeholder for platforms without inotify, always polling
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+struct Watch;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+impl Watch {
+    fn new(_path: &Path) -> Option<Watch> {
+        None
+    }
+
+    fn wait(&self, _duration: Duration) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant, SystemTime};
+
+    use crate::probe::{Probe, ProbeOutcome};
+
+    use super::with_reference_time;
+    use super::FileProbe;
+
+    fn scratch_directory(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "rust-for-it-test-filesystem-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        path
+    }
+
+    fn is_ready(probe: &dyn Probe) -> bool {
+        matches!(probe.attempt(Some(Duration::ZERO)), ProbeOutcome::Ready)
+    }
+
+    #[test]
+    fn test_file_probe_create_for_invalid() {
+        for service in [
+            "file:",
+            "file:?non-empty",
+            "file:/x?old",
+            "dir:/x?matches=(",
+        ] {
+            assert_eq!(
+                FileProbe::create_file(service).err().unwrap().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_probe() {
+        let directory = scratch_directory("file");
+        let path = directory.join("ready");
+        let service = format!("file:{}", path.display());
+
+        let probe = FileProbe::create_file(&service).unwrap();
+        assert_eq!(probe.activity(), format!("find file {}", path.display()));
+        let non_empty_probe = FileProbe::create_file(&format!("{service}?non-empty")).unwrap();
+        let matching_probe =
+            FileProbe::create_file(&format!("{service}?non-empty&matches=^state=(up|ok)$"))
+                .unwrap();
+        let directory_probe = FileProbe::create_directory(&format!("dir:{}", path.display()));
+        assert!(!is_ready(probe.as_ref()));
+
+        fs::write(&path, "").unwrap();
+        assert!(is_ready(probe.as_ref()));
+        assert!(!is_ready(non_empty_probe.as_ref()));
+        assert!(!is_ready(directory_probe.unwrap().as_ref()));
+
+        fs::write(&path, "state=down").unwrap();
+        assert!(is_ready(non_empty_probe.as_ref()));
+        assert!(!is_ready(matching_probe.as_ref()));
+
+        fs::write(&path, "state=up").unwrap();
+        assert!(is_ready(matching_probe.as_ref()));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_file_probe_for_newer() {
+        let directory = scratch_directory("newer");
+        let path = directory.join("ready");
+        fs::write(&path, "old").unwrap();
+        sleep(Duration::from_millis(50));
+
+        let probe = FileProbe::create_file(&format!("file:{}?newer", path.display())).unwrap();
+        assert!(!is_ready(probe.as_ref()));
+
+        sleep(Duration::from_millis(50));
+        fs::write(&path, "new").unwrap();
+        assert!(is_ready(probe.as_ref()));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_file_probe_for_newer_with_reference_time() {
+        let directory = scratch_directory("newer-reference");
+        let path = directory.join("ready");
+        let started = SystemTime::now();
+        sleep(Duration::from_millis(50));
+        fs::write(&path, "new").unwrap();
+        sleep(Duration::from_millis(50));
+
+        // NOTE: Probes created later keep referring to when waiting started
+        let service = format!("file:{}?newer", path.display());
+        let probe = with_reference_time(started, || FileProbe::create_file(&service)).unwrap();
+        assert!(is_ready(probe.as_ref()));
+        let probe = FileProbe::create_file(&service).unwrap();
+        assert!(!is_ready(probe.as_ref()));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_directory_probe() {
+        let directory = scratch_directory("dir");
+        let service = format!("dir:{}", directory.display());
+
+        assert!(is_ready(
+            FileProbe::create_directory(&service).unwrap().as_ref()
+        ));
+        let non_empty_probe = FileProbe::create_directory(&format!("{service}?non-empty")).unwrap();
+        let matching_probe =
+            FileProbe::create_directory(&format!("{service}?matches=\\.pem$")).unwrap();
+        assert!(!is_ready(non_empty_probe.as_ref()));
+
+        fs::write(directory.join("tls.key"), "").unwrap();
+        assert!(is_ready(non_empty_probe.as_ref()));
+        assert!(!is_ready(matching_probe.as_ref()));
+
+        fs::write(directory.join("tls.pem"), "").unwrap();
+        assert!(is_ready(matching_probe.as_ref()));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn test_file_probe_watches_for_changes() {
+        let directory = scratch_directory("watch");
+        let path = directory.join("sub").join("ready");
+        let probe = FileProbe::create_file(&format!("file:{}", path.display())).unwrap();
+
+        let writer = {
+            let (directory, path) = (directory.clone(), path.clone());
+            spawn(move || {
+                sleep(Duration::from_millis(200));
+                fs::create_dir(directory.join("sub")).unwrap();
+                fs::write(path, "").unwrap();
+            })
+        };
+
+        // NOTE: The first change is the creation of the "sub" directory
+        let timer = Instant::now();
+        let mut outcome = probe.attempt(Some(Duration::from_secs(3)));
+        if !matches!(outcome, ProbeOutcome::Ready) {
+            outcome = probe.attempt(Some(Duration::from_secs(3)));
+        }
+        assert!(matches!(outcome, ProbeOutcome::Ready));
+        assert!(timer.elapsed() < Duration::from_secs(2));
+
+        writer.join().unwrap();
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,462 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::network::{connect, resolve_host_and_port};
+use crate::probe::{Probe, ProbeOutcome};
+use crate::util::protocol_error;
+
+const CONNECTION_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// Upper bound for frames accepted from the server, the default of HTTP/2
+const MAX_FRAME_SIZE: usize = 16_384;
+
+const FRAME_TYPE_DATA: u8 = 0x0;
+const FRAME_TYPE_HEADERS: u8 = 0x1;
+const FRAME_TYPE_RST_STREAM: u8 = 0x3;
+const FRAME_TYPE_SETTINGS: u8 = 0x4;
+const FRAME_TYPE_PING: u8 = 0x6;
+const FRAME_TYPE_GOAWAY: u8 = 0x7;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+
+const STREAM_ID: u32 = 1;
+
+/// Values of enum `grpc.health.v1.HealthCheckResponse.ServingStatus`
+const SERVING_STATUS_NAMES: [&str; 4] = ["UNKNOWN", "SERVING", "NOT_SERVING", "SERVICE_UNKNOWN"];
+const SERVING: u64 = 1;
+
+/// Calls the standard gRPC health service via HTTP/2 without TLS and requires status
+/// SERVING, for services like "grpc://localhost:50051/package.Service" or
+/// "grpc://localhost:50051" for the health of the server as a whole
+pub(crate) struct GrpcHealthProbe {
+    addresses: Vec<SocketAddr>,
+    authority: String,
+    service_name: String,
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+fn write_frame(
+    stream: &mut TcpStream,
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<Frame> {
+    let mut header = [0u8; 9];
+    stream.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(protocol_error(format!(
+            "Frame of {length} bytes is too large"
+        )));
+    }
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload)?;
+    Ok(Frame {
+        kind: header[3],
+        flags: header[4],
+        stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+        payload,
+    })
+}
+
+/// HPACK integer with the given prefix bits in the first byte
+fn encode_integer(output: &mut Vec<u8>, first_byte: u8, prefix_bits: u32, mut value: usize) {
+    let prefix_max = (1usize << prefix_bits) - 1;
+    if value < prefix_max {
+        output.push(first_byte | value as u8);
+        return;
+    }
+    output.push(first_byte | prefix_max as u8);
+    value -= prefix_max;
+    while value >= 0x80 {
+        output.push((value % 0x80) as u8 | 0x80);
+        value /= 0x80;
+    }
+    output.push(value as u8);
+}
+
+/// HPACK string literal, without Huffman coding
+fn encode_string(output: &mut Vec<u8>, text: &str) {
+    encode_integer(output, 0x00, 7, text.len());
+    output.extend_from_slice(text.as_bytes());
+}
+
+/// HPACK header block of the request, using only the static table
+/// and literals that are not indexed, so that no state is involved
+fn encode_request_headers(authority: &str) -> Vec<u8> {
+    let mut block = Vec::new();
+    block.push(0x83); // :method POST
+    block.push(0x86); // :scheme http
+    encode_integer(&mut block, 0x00, 4, 4); // :path
+    encode_string(&mut block, HEALTH_CHECK_PATH);
+    encode_integer(&mut block, 0x00, 4, 1); // :authority
+    encode_string(&mut block, authority);
+    encode_integer(&mut block, 0x00, 4, 31); // content-type
+    encode_string(&mut block, "application/grpc");
+    block.push(0x00);
+    encode_string(&mut block, "te");
+    encode_string(&mut block, "trailers");
+    block
+}
+
+/// Protobuf varint, e.g. for field keys and lengths
+fn encode_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value % 0x80) as u8 | 0x80);
+        value /= 0x80;
+    }
+    output.push(value as u8);
+}
+
+fn decode_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Length-prefixed gRPC message holding a `grpc.health.v1.HealthCheckRequest`
+fn encode_health_check_request(service_name: &str) -> Vec<u8> {
+    let mut message = Vec::new();
+    if !service_name.is_empty() {
+        message.push(0x0a); // field 1, length-delimited
+        encode_varint(&mut message, service_name.len() as u64);
+        message.extend_from_slice(service_name.as_bytes());
+    }
+    let mut data = vec![0u8]; // not compressed
+    data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    data.extend_from_slice(&message);
+    data
+}
+
+/// Serving status from a length-prefixed `grpc.health.v1.HealthCheckResponse`
+fn decode_health_check_response(data: &[u8]) -> Result<u64, io::Error> {
+    let malformed = || protocol_error(String::from("Malformed health check response"));
+    if data.len() < 5 || data[0] != 0 {
+        return Err(malformed());
+    }
+    let length = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    let mut message = data.get(5..5 + length).ok_or_else(malformed)?;
+
+    let mut status = 0; // NOTE: The default of proto3 for fields left out
+    while !message.is_empty() {
+        let key = decode_varint(&mut message).ok_or_else(malformed)?;
+        match key & 0x7 {
+            0 => {
+                let value = decode_varint(&mut message).ok_or_else(malformed)?;
+                if key >> 3 == 1 {
+                    status = value;
+                }
+            }
+            2 => {
+                let length = decode_varint(&mut message).ok_or_else(malformed)? as usize;
+                message = message.get(length..).ok_or_else(malformed)?;
+            }
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(status)
+}
+
+/// Payload of a DATA frame without any padding
+fn data_of(frame: &Frame) -> Result<&[u8], io::Error> {
+    if frame.flags & FLAG_PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+    let (&padding, rest) = frame
+        .payload
+        .split_first()
+        .ok_or_else(|| protocol_error(String::from("Malformed padded frame")))?;
+    rest.get(..rest.len().saturating_sub(padding as usize))
+        .ok_or_else(|| protocol_error(String::from("Malformed padded frame")))
+}
+
+impl GrpcHealthProbe {
+    pub(crate) fn create(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let spec = service.split_once("://").map_or("", |(_, spec)| spec);
+        let (authority, service_name) = spec.split_once('/').unwrap_or((spec, ""));
+        if authority.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No host and port given",
+            ));
+        }
+        Ok(Box::new(GrpcHealthProbe {
+            addresses: resolve_host_and_port(authority)?,
+            authority: authority.to_string(),
+            service_name: service_name.to_string(),
+        }))
+    }
+
+    /// Serving status reported by the health service
+    fn check(&self, timeout: Option<Duration>) -> Result<u64, io::Error> {
+        let mut stream = connect(&self.addresses[0], timeout)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        stream.set_nodelay(true)?;
+
+        stream.write_all(CONNECTION_PREFACE)?;
+        write_frame(&mut stream, FRAME_TYPE_SETTINGS, 0, 0, &[])?;
+        write_frame(
+            &mut stream,
+            FRAME_TYPE_HEADERS,
+            FLAG_END_HEADERS,
+            STREAM_ID,
+            &encode_request_headers(&self.authority),
+        )?;
+        write_frame(
+            &mut stream,
+            FRAME_TYPE_DATA,
+            FLAG_END_STREAM,
+            STREAM_ID,
+            &encode_health_check_request(&self.service_name),
+        )?;
+
+        let mut data = Vec::new();
+        loop {
+            let frame = read_frame(&mut stream)?;
+            match frame.kind {
+                FRAME_TYPE_SETTINGS if frame.flags & FLAG_ACK == 0 => {
+                    write_frame(&mut stream, FRAME_TYPE_SETTINGS, FLAG_ACK, 0, &[])?;
+                }
+                FRAME_TYPE_PING if frame.flags & FLAG_ACK == 0 => {
+                    write_frame(&mut stream, FRAME_TYPE_PING, FLAG_ACK, 0, &frame.payload)?;
+                }
+                FRAME_TYPE_GOAWAY => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Server sent GOAWAY",
+                    ));
+                }
+                FRAME_TYPE_RST_STREAM if frame.stream_id == STREAM_ID => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        "Server reset the stream",
+                    ));
+                }
+                FRAME_TYPE_DATA if frame.stream_id == STREAM_ID => {
+                    data.extend_from_slice(data_of(&frame)?);
+                }
+                // NOTE: Headers are not decoded since that would need HPACK state;
+                //       the response message alone tells the serving status.
+                FRAME_TYPE_HEADERS if frame.stream_id == STREAM_ID => {}
+                _ => {}
+            }
+            if frame.stream_id == STREAM_ID
+                && matches!(frame.kind, FRAME_TYPE_DATA | FRAME_TYPE_HEADERS)
+                && frame.flags & FLAG_END_STREAM != 0
+            {
+                break;
+            }
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+
+        if data.is_empty() {
+            return Err(protocol_error(String::from(
+                "No health check response, e.g. for a server without the health service",
+            )));
+        }
+        decode_health_check_response(&data)
+    }
+}
+
+impl Probe for GrpcHealthProbe {
+    fn activity(&self) -> String {
+        let service_name = match self.service_name.as_str() {
+            "" => "the server",
+            service_name => service_name,
+        };
+        format!(
+            "check the health of {service_name} at {}",
+            self.addresses[0]
+        )
+    }
+
+    fn attempt(&self, timeout: Option<Duration>) -> ProbeOutcome {
+        match self.check(timeout) {
+            Ok(SERVING) => ProbeOutcome::Ready,
+            Ok(status) => {
+                let status_name = SERVING_STATUS_NAMES
+                    .get(status as usize)
+                    .map_or(status.to_string(), |name| name.to_string());
+                ProbeOutcome::NotYet(io::Error::other(format!("Status {status_name}")))
+            }
+            Err(error) => ProbeOutcome::NotYet(error),
+        }
+    }
+
+    fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    use crate::probe::ProbeOutcome;
+
+    use super::decode_health_check_response;
+    use super::encode_health_check_request;
+    use super::encode_integer;
+    use super::read_frame;
+    use super::write_frame;
+    use super::GrpcHealthProbe;
+    use super::{CONNECTION_PREFACE, FLAG_END_HEADERS, FLAG_END_STREAM};
+    use super::{FRAME_TYPE_DATA, FRAME_TYPE_HEADERS, FRAME_TYPE_RST_STREAM};
+    use super::{FRAME_TYPE_SETTINGS, STREAM_ID};
+
+    #[test]
+    fn test_encode_integer() {
+        let mut output = Vec::new();
+        encode_integer(&mut output, 0x00, 4, 31);
+        encode_integer(&mut output, 0x00, 5, 1337);
+        assert_eq!(output, [0x0f, 0x10, 0x1f, 0x9a, 0x0a]);
+    }
+
+    #[test]
+    fn test_health_check_messages() {
+        assert_eq!(encode_health_check_request(""), [0, 0, 0, 0, 0]);
+        assert_eq!(
+            encode_health_check_request("a.B"),
+            [0, 0, 0, 0, 5, 0x0a, 3, b'a', b'.', b'B']
+        );
+        assert_eq!(
+            decode_health_check_response(&[0, 0, 0, 0, 2, 0x08, 1]).unwrap(),
+            1
+        );
+        assert_eq!(decode_health_check_response(&[0, 0, 0, 0, 0]).unwrap(), 0);
+        assert!(decode_health_check_response(&[0, 0, 0, 0, 2, 0x08]).is_err());
+    }
+
+    /// Answers health checks with the given status, or resets the stream for `None`
+    fn start_health_server(status: Option<u8>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = serve_health_check(&mut stream, status);
+            }
+        });
+        port
+    }
+
+    fn serve_health_check(stream: &mut TcpStream, status: Option<u8>) -> io::Result<()> {
+        let mut preface = [0u8; 24];
+        stream.read_exact(&mut preface)?;
+        assert_eq!(preface, CONNECTION_PREFACE);
+        write_frame(stream, FRAME_TYPE_SETTINGS, 0, 0, &[])?;
+        loop {
+            let frame = read_frame(stream)?;
+            if frame.kind == FRAME_TYPE_DATA && frame.flags & FLAG_END_STREAM != 0 {
+                break;
+            }
+        }
+
+        let Some(status) = status else {
+            return write_frame(stream, FRAME_TYPE_RST_STREAM, 0, STREAM_ID, &[0, 0, 0, 2]);
+        };
+        write_frame(
+            stream,
+            FRAME_TYPE_HEADERS,
+            FLAG_END_HEADERS,
+            STREAM_ID,
+            &[0x88],
+        )?;
+        let message = [0, 0, 0, 0, 2, 0x08, status];
+        write_frame(stream, FRAME_TYPE_DATA, 0, STREAM_ID, &message)?;
+        let mut trailers = vec![0x00, 11];
+        trailers.extend_from_slice(b"grpc-status");
+        trailers.extend_from_slice(&[1, b'0']);
+        write_frame(
+            stream,
+            FRAME_TYPE_HEADERS,
+            FLAG_END_HEADERS | FLAG_END_STREAM,
+            STREAM_ID,
+            &trailers,
+        )?;
+        // NOTE: Closing with unread input, e.g. a settings ACK, would reset the connection
+        stream.shutdown(Shutdown::Write)?;
+        io::copy(stream, &mut io::sink()).map(|_| ())
+    }
+
+    fn attempt(service: &str) -> ProbeOutcome {
+        GrpcHealthProbe::create(service)
+            .unwrap()
+            .attempt(Some(Duration::from_secs(5)))
+    }
+
+    #[test]
+    fn test_grpc_health_probe() {
+        let port = start_health_server(Some(1));
+        let probe = GrpcHealthProbe::create(&format!("grpc://127.0.0.1:{port}/a.B")).unwrap();
+        assert_eq!(
+            probe.activity(),
+            format!("check the health of a.B at 127.0.0.1:{port}")
+        );
+        assert!(matches!(
+            attempt(&format!("grpc://127.0.0.1:{port}/a.B")),
+            ProbeOutcome::Ready
+        ));
+        assert!(matches!(
+            attempt(&format!("grpc://127.0.0.1:{port}")),
+            ProbeOutcome::Ready
+        ));
+    }
+
+    #[test]
+    fn test_grpc_health_probe_for_bad() {
+        let port = start_health_server(Some(2));
+        match attempt(&format!("grpc://127.0.0.1:{port}")) {
+            ProbeOutcome::NotYet(error) => assert_eq!(error.to_string(), "Status NOT_SERVING"),
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+
+        let port = start_health_server(None);
+        match attempt(&format!("grpc://127.0.0.1:{port}")) {
+            ProbeOutcome::NotYet(error) => assert_eq!(error.kind(), io::ErrorKind::ConnectionReset),
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+
+        assert_eq!(
+            GrpcHealthProbe::create("grpc:///a.B").err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use rust_for_it::WaitReport;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Escapes markup and replaces characters that XML 1.0 does not allow,
+/// e.g. control characters in the output of a "cmd:" service
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {
+                escaped.push(char::REPLACEMENT_CHARACTER)
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders one JUnit testcase per service, in the dialect
+/// that GitLab and Jenkins understand
+pub(crate) fn render_junit_report(reports: &[WaitReport], elapsed: Duration) -> String {
+    let tests = reports.len();
+    let failures = reports
+        .iter()
+        .filter(|report| report.result.is_err())
+        .count();
+    let time = elapsed.as_secs_f64();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{tests}\" failures=\"{failures}\" time=\"{time:.3}\">"
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"rust-for-it\" tests=\"{tests}\" failures=\"{failures}\" time=\"{time:.3}\">"
+    );
+    for report in reports {
+        let name = escape_xml(&report.service);
+        let time = report.elapsed.as_secs_f64();
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{name}\" classname=\"rust-for-it\" time=\"{time:.3}\""
+        );
+        match report.result {
+            Ok(_) => xml.push_str("/>\n"),
+            Err(ref error) => {
+                let error_kind = escape_xml(&format!("{:?}", error.kind()));
+                let message = escape_xml(report.failure_message.as_deref().unwrap_or_default());
+                let _ = writeln!(xml, ">");
+                let _ = writeln!(
+                    xml,
+                    "      <failure type=\"{error_kind}\" message=\"{message}\">{message}</failure>"
+                );
+                xml.push_str("    </testcase>\n");
+            }
+        }
+    }
+    xml.push_str("  </testsuite>\n");
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+pub(crate) fn write_junit_report(
+    path: &Path,
+    reports: &[WaitReport],
+    elapsed: Duration,
+) -> Result<(), std::io::Error> {
+    fs::write(path, render_junit_report(reports, elapsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use rust_for_it::WaitReport;
+
+    use std::io;
+    use std::time::Duration;
+
+    use super::escape_xml;
+    use super::render_junit_report;
+    use super::write_junit_report;
+
+    fn example_reports() -> Vec<WaitReport> {
+        let mut good = WaitReport::new("good:1");
+        good.elapsed = Duration::from_millis(100);
+
+        let mut bad = WaitReport::new("bad:2");
+        bad.elapsed = Duration::from_millis(2500);
+        bad.result = Err(io::Error::new(io::ErrorKind::TimedOut, "Time is up"));
+        bad.failure_message = Some(String::from(
+            "bad:2 timed out after waiting for 2 seconds (<x>).",
+        ));
+
+        vec![good, bad]
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;"
+        );
+        assert_eq!(
+            escape_xml("a\tb\nc\u{1b}[0m\u{0}\u{ffff}"),
+            "a\tb\nc\u{fffd}[0m\u{fffd}\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn test_render_junit_report() {
+        assert_eq!(
+            render_junit_report(&example_reports(), Duration::from_millis(2600)),
+            indoc! {r#"
+                <?xml version="1.0" encoding="UTF-8"?>
+                <testsuites tests="2" failures="1" time="2.600">
+                  <testsuite name="rust-for-it" tests="2" failures="1" time="2.600">
+                    <testcase name="good:1" classname="rust-for-it" time="0.100"/>
+                    <testcase name="bad:2" classname="rust-for-it" time="2.500">
+                      <failure type="TimedOut" message="bad:2 timed out after waiting for 2 seconds (&lt;x&gt;).">bad:2 timed out after waiting for 2 seconds (&lt;x&gt;).</failure>
+                    </testcase>
+                  </testsuite>
+                </testsuites>
+            "#}
+        );
+    }
+
+    #[test]
+    fn test_write_junit_report() {
+        let path =
+            std::env::temp_dir().join(format!("rust-for-it-test-junit-{}.xml", std::process::id()));
+        let reports = example_reports();
+
+        write_junit_report(&path, &reports, Duration::ZERO).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(content, render_junit_report(&reports, Duration::ZERO));
+        assert!(write_junit_report(
+            &std::env::temp_dir().join("no-such-directory/junit.xml"),
+            &reports,
+            Duration::ZERO
+        )
+        .is_err());
+    }
+}
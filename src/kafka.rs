@@ -0,0 +1,369 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::network::{connect, resolve_host_with_default_port};
+use crate::probe::{Probe, ProbeOutcome};
+use crate::util::{invalid_input, protocol_error, take, take_i16, take_i32};
+
+const CLIENT_ID: &str = "rust-for-it";
+
+const DEFAULT_PORT: u16 = 9092;
+
+/// Upper bound for responses accepted from the broker
+const MAX_RESPONSE_SIZE: usize = 1_048_576;
+
+const API_KEY_METADATA: i16 = 3;
+const API_KEY_API_VERSIONS: i16 = 18;
+
+/// NOTE: Version 4 is the first that can ask the broker not to create missing topics
+const METADATA_VERSION: i16 = 4;
+
+const ERROR_NONE: i16 = 0;
+
+/// Checks that a Kafka broker answers an `ApiVersions` request, for services like
+/// "kafka://localhost:9092", or with option "?topic=<name>" that the topic exists
+/// and has a leader for each of its partitions
+pub(crate) struct KafkaProbe {
+    addresses: Vec<SocketAddr>,
+    topic: Option<String>,
+}
+
+fn not_ready(text: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, text)
+}
+
+fn put_string(output: &mut Vec<u8>, text: &str) {
+    output.extend_from_slice(&(text.len() as i16).to_be_bytes());
+    output.extend_from_slice(text.as_bytes());
+}
+
+/// Takes a string, `None` for null
+fn take_string(input: &mut &[u8]) -> Result<Option<String>, io::Error> {
+    let length = take_i16(input)?;
+    if length < 0 {
+        return Ok(None);
+    }
+    let bytes = take(input, length as usize)?;
+    Ok(Some(String::from_utf8_lossy(bytes).to_string()))
+}
+
+/// Takes the element count of an array, zero for null
+fn take_array_length(input: &mut &[u8]) -> Result<usize, io::Error> {
+    Ok(take_i32(input)?.max(0) as usize)
+}
+
+/// Sends a request with header version 1 and returns the response body
+fn call(
+    stream: &mut TcpStream,
+    api_key: i16,
+    api_version: i16,
+    correlation_id: i32,
+    body: &[u8],
+) -> io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    request.extend_from_slice(&api_key.to_be_bytes());
+    request.extend_from_slice(&api_version.to_be_bytes());
+    request.extend_from_slice(&correlation_id.to_be_bytes());
+    put_string(&mut request, CLIENT_ID);
+    request.extend_from_slice(body);
+    let mut message = (request.len() as i32).to_be_bytes().to_vec();
+    message.extend_from_slice(&request);
+    stream.write_all(&message)?;
+
+    let mut size = [0u8; 4];
+    stream.read_exact(&mut size)?;
+    let size = i32::from_be_bytes(size).max(0) as usize;
+    if size > MAX_RESPONSE_SIZE {
+        return Err(protocol_error(format!(
+            "Response of {size} bytes is too large"
+        )));
+    }
+    let mut response = vec![0u8; size];
+    stream.read_exact(&mut response)?;
+    let mut input = response.as_slice();
+    if take_i32(&mut input)? != correlation_id {
+        return Err(protocol_error(String::from(
+            "Response does not match the request",
+        )));
+    }
+    Ok(input.to_vec())
+}
+
+/// Checks a response to `ApiVersions` version 0
+fn check_api_versions(response: &[u8]) -> Result<(), io::Error> {
+    let mut input = response;
+    let error_code = take_i16(&mut input)?;
+    if error_code != ERROR_NONE {
+        return Err(protocol_error(format!(
+            "ApiVersions failed with error code {error_code}"
+        )));
+    }
+    let api_count = take_array_length(&mut input)?;
+    take(&mut input, api_count * 6)?;
+    Ok(())
+}
+
+/// Checks a response to `Metadata` version 4 for a single topic
+fn check_topic(response: &[u8], topic: &str) -> Result<(), io::Error> {
+    let mut input = response;
+    take_i32(&mut input)?; // throttle time
+    for _ in 0..take_array_length(&mut input)? {
+        take_i32(&mut input)?; // node ID
+        take_string(&mut input)?; // host
+        take_i32(&mut input)?; // port
+        take_string(&mut input)?; // rack
+    }
+    take_string(&mut input)?; // cluster ID
+    take_i32(&mut input)?; // controller ID
+
+    for _ in 0..take_array_length(&mut input)? {
+        let error_code = take_i16(&mut input)?;
+        let name = take_string(&mut input)?;
+        take(&mut input, 1)?; // is internal
+        let mut partitions_without_leader = Vec::new();
+        let partition_count = take_array_length(&mut input)?;
+        for _ in 0..partition_count {
+            let partition_error_code = take_i16(&mut input)?;
+            let partition_index = take_i32(&mut input)?;
+            let leader_id = take_i32(&mut input)?;
+            for _ in 0..2 {
+                let node_count = take_array_length(&mut input)?;
+                take(&mut input, node_count * 4)?; // replica and in-sync nodes
+            }
+            if partition_error_code != ERROR_NONE || leader_id < 0 {
+                partitions_without_leader.push(partition_index.to_string());
+            }
+        }
+        if name.as_deref() != Some(topic) {
+            continue;
+        }
+        if error_code != ERROR_NONE {
+            return Err(not_ready(format!(
+                "Topic \"{topic}\" is not available (error code {error_code})"
+            )));
+        }
+        if partition_count == 0 {
+            return Err(not_ready(format!("Topic \"{topic}\" has no partitions")));
+        }
+        if !partitions_without_leader.is_empty() {
+            return Err(not_ready(format!(
+                "Topic \"{topic}\" has no leader for partition {}",
+                partitions_without_leader.join(", ")
+            )));
+        }
+        return Ok(());
+    }
+    Err(not_ready(format!("Topic \"{topic}\" is missing")))
+}
+
+impl KafkaProbe {
+    /// Resolves "kafka://host[:port][?topic=<name>]" once
+    pub(crate) fn create(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let spec = service.split_once("://").map_or("", |(_, spec)| spec);
+        let (host_and_port, topic) = match spec.split_once('?') {
+            Some((host_and_port, option)) => match option.strip_prefix("topic=") {
+                Some(topic) if !topic.is_empty() => (host_and_port, Some(topic.to_string())),
+                _ => return Err(invalid_input(format!("Unknown option \"{option}\""))),
+            },
+            None => (spec, None),
+        };
+        if host_and_port.is_empty() {
+            return Err(invalid_input(String::from("No host given")));
+        }
+        Ok(Box::new(KafkaProbe {
+            addresses: resolve_host_with_default_port(host_and_port, DEFAULT_PORT)?,
+            topic,
+        }))
+    }
+
+    fn check(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let mut stream = connect(&self.addresses[0], timeout)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+
+        let response = call(&mut stream, API_KEY_API_VERSIONS, 0, 1, &[])?;
+        check_api_versions(&response)?;
+
+        if let Some(topic) = &self.topic {
+            let mut body = 1i32.to_be_bytes().to_vec();
+            put_string(&mut body, topic);
+            body.push(0); // do not create the topic
+            let response = call(&mut stream, API_KEY_METADATA, METADATA_VERSION, 2, &body)?;
+            check_topic(&response, topic)?;
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+        Ok(())
+    }
+}
+
+impl Probe for KafkaProbe {
+    fn activity(&self) -> String {
+        match &self.topic {
+            Some(topic) => format!(
+                "find topic \"{topic}\" at Kafka broker {}",
+                self.addresses[0]
+            ),
+            None => format!("query Kafka broker {}", self.addresses[0]),
+        }
+    }
+
+    fn attempt(&self, timeout: Option<Duration>) -> ProbeOutcome {
+        match self.check(timeout) {
+            Ok(()) => ProbeOutcome::Ready,
+            Err(error) => ProbeOutcome::NotYet(error),
+        }
+    }
+
+    fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    use crate::probe::ProbeOutcome;
+    use crate::util::{take_i16, take_i32};
+
+    use super::put_string;
+    use super::KafkaProbe;
+    use super::{API_KEY_API_VERSIONS, API_KEY_METADATA};
+
+    fn put_partition(output: &mut Vec<u8>, index: i32, leader_id: i32) {
+        output.extend_from_slice(&0i16.to_be_bytes());
+        output.extend_from_slice(&index.to_be_bytes());
+        output.extend_from_slice(&leader_id.to_be_bytes());
+        output.extend_from_slice(&1i32.to_be_bytes());
+        output.extend_from_slice(&leader_id.max(1).to_be_bytes());
+        output.extend_from_slice(&0i32.to_be_bytes());
+    }
+
+    /// Plays a broker knowing topic "orders" with a leader for each partition
+    /// and topic "audit" with no leader for partition 1
+    fn serve_kafka(stream: &mut TcpStream) -> io::Result<()> {
+        loop {
+            let mut size = [0u8; 4];
+            stream.read_exact(&mut size)?;
+            let mut request = vec![0u8; i32::from_be_bytes(size) as usize];
+            stream.read_exact(&mut request)?;
+            let mut input = request.as_slice();
+            let api_key = take_i16(&mut input)?;
+            take_i16(&mut input)?;
+            let correlation_id = take_i32(&mut input)?;
+            input = &input[2 + 11..]; // client ID
+
+            let mut response = correlation_id.to_be_bytes().to_vec();
+            match api_key {
+                API_KEY_API_VERSIONS => {
+                    response.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0, 18, 0, 0, 0, 3]);
+                }
+                API_KEY_METADATA => {
+                    let topic = String::from_utf8_lossy(&input[6..input.len() - 1]).to_string();
+                    response.extend_from_slice(&0i32.to_be_bytes()); // throttle time
+                    response.extend_from_slice(&1i32.to_be_bytes());
+                    response.extend_from_slice(&1i32.to_be_bytes());
+                    put_string(&mut response, "localhost");
+                    response.extend_from_slice(&9092i32.to_be_bytes());
+                    response.extend_from_slice(&(-1i16).to_be_bytes()); // rack
+                    put_string(&mut response, "cluster");
+                    response.extend_from_slice(&1i32.to_be_bytes()); // controller
+                    response.extend_from_slice(&1i32.to_be_bytes());
+                    let error_code: i16 = match topic.as_str() {
+                        "orders" | "audit" => 0,
+                        _ => 3,
+                    };
+                    response.extend_from_slice(&error_code.to_be_bytes());
+                    put_string(&mut response, &topic);
+                    response.push(0);
+                    response.extend_from_slice(&2i32.to_be_bytes());
+                    put_partition(&mut response, 0, 1);
+                    put_partition(&mut response, 1, if topic == "audit" { -1 } else { 1 });
+                }
+                _ => panic!("Unexpected API key {api_key}"),
+            }
+            let mut message = (response.len() as i32).to_be_bytes().to_vec();
+            message.extend_from_slice(&response);
+            stream.write_all(&message)?;
+        }
+    }
+
+    fn start_kafka_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = serve_kafka(&mut stream);
+            }
+        });
+        port
+    }
+
+    fn attempt(service: &str) -> ProbeOutcome {
+        KafkaProbe::create(service)
+            .unwrap()
+            .attempt(Some(Duration::from_secs(5)))
+    }
+
+    #[test]
+    fn test_kafka_probe() {
+        let port = start_kafka_server();
+        let probe = KafkaProbe::create(&format!("kafka://127.0.0.1:{port}")).unwrap();
+        assert_eq!(
+            probe.activity(),
+            format!("query Kafka broker 127.0.0.1:{port}")
+        );
+        assert!(matches!(
+            attempt(&format!("kafka://127.0.0.1:{port}")),
+            ProbeOutcome::Ready
+        ));
+        assert!(matches!(
+            attempt(&format!("kafka://127.0.0.1:{port}?topic=orders")),
+            ProbeOutcome::Ready
+        ));
+    }
+
+    #[test]
+    fn test_kafka_probe_for_bad_topics() {
+        let port = start_kafka_server();
+        for (topic, expected_error) in [
+            ("audit", "Topic \"audit\" has no leader for partition 1"),
+            (
+                "missing",
+                "Topic \"missing\" is not available (error code 3)",
+            ),
+        ] {
+            match attempt(&format!("kafka://127.0.0.1:{port}?topic={topic}")) {
+                ProbeOutcome::NotYet(error) => assert_eq!(error.to_string(), expected_error),
+                outcome => panic!("Expected NotYet, got {outcome:?}"),
+            }
+        }
+        for service in [
+            "kafka://",
+            "kafka://127.0.0.1:9092?topic=",
+            "kafka://127.0.0.1:9092?x",
+        ] {
+            assert_eq!(
+                KafkaProbe::create(service).err().unwrap().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn test_kafka_probe_default_port() {
+        let probe = KafkaProbe::create("kafka://127.0.0.1").unwrap();
+        assert_eq!(probe.addresses()[0].port(), 9092);
+        let probe = KafkaProbe::create("kafka://[::1]?topic=orders").unwrap();
+        assert_eq!(probe.addresses()[0].port(), 9092);
+    }
+}
@@ -0,0 +1,52 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+//! Wait for one or more services to be available, as a library.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use rust_for_it::{WaitEvent, Waiter};
+//!
+//! let report = Waiter::new()
+//!     .service("localhost:5432")
+//!     .service("[::1]:6379")
+//!     .timeout(Duration::from_secs(30))
+//!     .on_event(|event| {
+//!         if let WaitEvent::Available { service, .. } = event {
+//!             println!("{service} is available.");
+//!         }
+//!     })
+//!     .wait();
+//! assert!(report.is_success());
+//! ```
+
+mod amqp;
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod elasticsearch;
+mod filesystem;
+mod grpc;
+mod kafka;
+mod mongodb;
+mod network;
+mod probe;
+mod process;
+mod script;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "tls")]
+mod tls;
+mod util;
+mod waiter;
+
+#[cfg(feature = "tokio")]
+pub use asynchronous::wait_for;
+pub use network::{display_form_of, probe_service, probe_service_since, WaitEvent, WaitReport};
+pub use probe::{
+    register_probe_scheme, register_url_probe_scheme, registered_scheme_of, Probe, ProbeFactory,
+    ProbeOutcome,
+};
+pub use waiter::{Policy, Report, Waiter};
@@ -0,0 +1,253 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use log::kv::{Error as KvError, Key, Value, VisitSource};
+use log::{Level, Record};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+
+pub(crate) const SYSLOG_IDENTIFIER: &str = "rust-for-it";
+
+#[cfg(unix)]
+pub(crate) const JOURNALD_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+#[cfg(unix)]
+pub(crate) const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum LogTarget {
+    /// Status to stdout, failures to stderr
+    Console,
+    /// Everything to stderr, e.g. to keep stdout to the command
+    Stderr,
+    /// Native protocol of the systemd journal, with structured fields
+    Journald,
+    /// Local syslog daemon, RFC 3164 style
+    Syslog,
+}
+
+pub(crate) fn parse_log_target(text: &str) -> Result<LogTarget, String> {
+    match text {
+        "console" => Ok(LogTarget::Console),
+        "stderr" => Ok(LogTarget::Stderr),
+        #[cfg(unix)]
+        "journald" => Ok(LogTarget::Journald),
+        #[cfg(unix)]
+        "syslog" => Ok(LogTarget::Syslog),
+        #[cfg(not(unix))]
+        "journald" | "syslog" => Err(String::from("is not supported on this platform.")),
+        _ => Err(String::from(
+            "is none of \"console\", \"stderr\", \"journald\" or \"syslog\".",
+        )),
+    }
+}
+
+/// Severity as in RFC 5424, as understood by both syslog and journald
+fn severity_of(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Turns a key like "error_kind" into a journald field name like "ERROR_KIND"
+fn journald_field_name_of(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+    name.trim_start_matches('_').to_string()
+}
+
+fn append_journald_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        // NOTE: Multi-line values need the binary encoding
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+struct JournaldFieldWriter {
+    datagram: Vec<u8>,
+}
+
+impl<'kvs> VisitSource<'kvs> for JournaldFieldWriter {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        let name = journald_field_name_of(key.as_str());
+        if key.as_str() != "sublevel" && !name.is_empty() {
+            append_journald_field(&mut self.datagram, &name, &value.to_string());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn format_journald_datagram(record: &Record) -> Vec<u8> {
+    let mut writer = JournaldFieldWriter {
+        datagram: Vec::new(),
+    };
+    append_journald_field(&mut writer.datagram, "MESSAGE", &record.args().to_string());
+    append_journald_field(
+        &mut writer.datagram,
+        "PRIORITY",
+        &severity_of(record.level()).to_string(),
+    );
+    append_journald_field(&mut writer.datagram, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+    let _ = record.key_values().visit(&mut writer);
+    writer.datagram
+}
+
+pub(crate) fn format_syslog_datagram(record: &Record, pid: u32) -> Vec<u8> {
+    const FACILITY_USER: u8 = 1;
+    let priority = FACILITY_USER * 8 + severity_of(record.level());
+    format!("<{priority}>{SYSLOG_IDENTIFIER}[{pid}]: {}", record.args()).into_bytes()
+}
+
+/// Connection to a local journald or syslog socket
+#[cfg(unix)]
+pub(crate) struct DatagramSink {
+    log_target: LogTarget,
+    socket: UnixDatagram,
+}
+
+#[cfg(unix)]
+impl DatagramSink {
+    pub(crate) fn connect(log_target: LogTarget, path: &Path) -> Result<Self, std::io::Error> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(DatagramSink { log_target, socket })
+    }
+
+    pub(crate) fn send(&self, record: &Record) -> Result<(), std::io::Error> {
+        let datagram = match self.log_target {
+            LogTarget::Journald => format_journald_datagram(record),
+            _ => format_syslog_datagram(record, std::process::id()),
+        };
+        self.socket.send(&datagram).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+
+    use super::format_journald_datagram;
+    use super::format_syslog_datagram;
+    use super::journald_field_name_of;
+    use super::parse_log_target;
+    use super::LogTarget;
+
+    #[test]
+    fn test_parse_log_target() {
+        assert_eq!(parse_log_target("console"), Ok(LogTarget::Console));
+        assert_eq!(parse_log_target("stderr"), Ok(LogTarget::Stderr));
+        #[cfg(unix)]
+        assert_eq!(parse_log_target("journald"), Ok(LogTarget::Journald));
+        #[cfg(unix)]
+        assert_eq!(parse_log_target("syslog"), Ok(LogTarget::Syslog));
+        assert!(parse_log_target("nope").is_err());
+    }
+
+    #[test]
+    fn test_journald_field_name_of() {
+        assert_eq!(journald_field_name_of("error_kind"), "ERROR_KIND");
+        assert_eq!(journald_field_name_of("_x-y"), "X_Y");
+    }
+
+    #[test]
+    fn test_format_journald_datagram() {
+        let key_values = [
+            ("sublevel", log::kv::Value::from(1u64)),
+            ("service", log::kv::Value::from("h:1")),
+            ("elapsed", log::kv::Value::from(0.5)),
+            ("error", log::kv::Value::from("a\nb")),
+        ];
+        let record = Record::builder()
+            .level(Level::Info)
+            .args(format_args!("h:1 is available"))
+            .key_values(&key_values)
+            .build();
+
+        let mut expected = b"MESSAGE=h:1 is available\n\
+            PRIORITY=6\n\
+            SYSLOG_IDENTIFIER=rust-for-it\n\
+            SERVICE=h:1\n\
+            ELAPSED=0.5\n\
+            ERROR\n"
+            .to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+
+        assert_eq!(format_journald_datagram(&record), expected);
+    }
+
+    #[test]
+    fn test_format_syslog_datagram() {
+        let record = Record::builder()
+            .level(Level::Error)
+            .args(format_args!("h:1 timed out"))
+            .build();
+        assert_eq!(
+            format_syslog_datagram(&record, 123),
+            b"<11>rust-for-it[123]: h:1 timed out"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_datagram_sink() {
+        use std::os::unix::net::UnixDatagram;
+
+        use super::DatagramSink;
+
+        let path = std::env::temp_dir().join(format!(
+            "rust-for-it-test-log-targets-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let record = Record::builder()
+            .level(Level::Info)
+            .args(format_args!("hello"))
+            .build();
+        DatagramSink::connect(LogTarget::Syslog, &path)
+            .unwrap()
+            .send(&record)
+            .unwrap();
+        DatagramSink::connect(LogTarget::Journald, &path)
+            .unwrap()
+            .send(&record)
+            .unwrap();
+
+        let mut buffer = [0u8; 1024];
+        let length = server.recv(&mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..length],
+            format!("<14>rust-for-it[{}]: hello", std::process::id()).as_bytes()
+        );
+        let length = server.recv(&mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..length],
+            b"MESSAGE=hello\nPRIORITY=6\nSYSLOG_IDENTIFIER=rust-for-it\n"
+        );
+
+        let _ = std::fs::remove_file(&path);
+        assert!(DatagramSink::connect(LogTarget::Syslog, &path).is_err());
+    }
+}
@@ -4,32 +4,69 @@
 // SPDX-License-Identifier: MIT
 
 use anstream::stream::RawStream;
-use log::{kv::ToValue, kv::Value, set_logger, set_max_level, LevelFilter, Log, Metadata, Record};
+use log::kv::{Error as KvError, Key, ToValue, Value, VisitSource, VisitValue};
+use log::{set_logger, set_max_level, Level, LevelFilter, Log, Metadata, Record};
 use once_cell::sync::Lazy;
 
 use std::collections::HashSet;
+use std::fmt::Write;
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 use std::thread::ThreadId;
+use std::time::Instant;
+
+use crate::dashboard::Dashboard;
+use crate::log_targets::LogTarget;
+#[cfg(unix)]
+use crate::log_targets::{DatagramSink, JOURNALD_SOCKET_PATH, SYSLOG_SOCKET_PATH};
+use crate::timestamp::{format_timestamp, TimestampFormat};
 
 static CUSTOM_LOG: CustomLog = CustomLog {};
 
 struct CustomLog {}
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+}
+
 struct LogConfig {
-    stdout: Option<Arc<Mutex<&'static mut dyn RawStream>>>,
-    stderr: Option<Arc<Mutex<&'static mut dyn RawStream>>>,
+    stdout: Option<Arc<Mutex<&'static mut (dyn RawStream + Send)>>>,
+    stderr: Option<Arc<Mutex<&'static mut (dyn RawStream + Send)>>>,
+    output_format: OutputFormat,
+    dashboard: Option<Dashboard>,
+    // NOTE: This can be lower than log::max_level() while the dashboard
+    //       needs to see debug records that are not meant to be printed
+    max_log_level: LevelFilter,
+    timestamp_format: Option<TimestampFormat>,
+    line_template: Option<String>,
+    started: Option<Instant>,
+    log_target: LogTarget,
+    #[cfg(unix)]
+    datagram_sink: Option<DatagramSink>,
 }
 
-static mut LOG_CONFIG: Mutex<LogConfig> = Mutex::new(LogConfig {
+static LOG_CONFIG: Mutex<LogConfig> = Mutex::new(LogConfig {
     stdout: None,
     stderr: None,
+    output_format: OutputFormat::Text,
+    dashboard: None,
+    max_log_level: LevelFilter::Off,
+    timestamp_format: None,
+    line_template: None,
+    started: None,
+    log_target: LogTarget::Console,
+    #[cfg(unix)]
+    datagram_sink: None,
 });
 
-static mut LOG_ACTIVE: Mutex<()> = Mutex::new(());
+static LOG_ACTIVE: Mutex<()> = Mutex::new(());
 
-static mut INCLUDED_THREADS: Lazy<Mutex<HashSet<ThreadId>>> =
+static INCLUDED_THREADS: Lazy<Mutex<HashSet<ThreadId>>> =
     Lazy::new(|| Mutex::new(HashSet::<ThreadId>::new()));
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -37,6 +74,8 @@ pub(crate) enum SubLevel {
     Starting,
     Succeeded,
     Failed,
+    Debug,
+    Trace,
 }
 
 impl From<u64> for SubLevel {
@@ -44,19 +83,208 @@ impl From<u64> for SubLevel {
         match value {
             0 => SubLevel::Starting,
             1 => SubLevel::Succeeded,
+            3 => SubLevel::Debug,
+            4 => SubLevel::Trace,
             _ => SubLevel::Failed,
         }
     }
 }
 
+impl From<Level> for SubLevel {
+    /// Default for records that are not tagged with a sublevel
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error | Level::Warn | Level::Info => SubLevel::Failed,
+            Level::Debug => SubLevel::Debug,
+            Level::Trace => SubLevel::Trace,
+        }
+    }
+}
+
 impl ToValue for SubLevel {
     fn to_value(&self) -> Value {
         match self {
             SubLevel::Starting => 0u64.to_value(),
             SubLevel::Succeeded => 1u64.to_value(),
             SubLevel::Failed => 2u64.to_value(),
+            SubLevel::Debug => 3u64.to_value(),
+            SubLevel::Trace => 4u64.to_value(),
+        }
+    }
+}
+
+fn write_json_string(json: &mut String, text: &str) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
         }
     }
+    json.push('"');
+}
+
+struct JsonValueWriter<'a> {
+    json: &'a mut String,
+}
+
+impl<'v> VisitValue<'v> for JsonValueWriter<'_> {
+    fn visit_any(&mut self, value: Value) -> Result<(), KvError> {
+        write_json_string(self.json, &value.to_string());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), KvError> {
+        self.json.push_str("null");
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), KvError> {
+        let _ = write!(self.json, "{value}");
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), KvError> {
+        let _ = write!(self.json, "{value}");
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), KvError> {
+        if value.is_finite() {
+            let _ = write!(self.json, "{value}");
+        } else {
+            self.json.push_str("null");
+        }
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), KvError> {
+        let _ = write!(self.json, "{value}");
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), KvError> {
+        write_json_string(self.json, value);
+        Ok(())
+    }
+}
+
+struct JsonObjectWriter {
+    json: String,
+}
+
+impl<'kvs> VisitSource<'kvs> for JsonObjectWriter {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), KvError> {
+        if key.as_str() == "sublevel" {
+            return Ok(());
+        }
+        self.json.push(',');
+        write_json_string(&mut self.json, key.as_str());
+        self.json.push(':');
+        value.visit(JsonValueWriter {
+            json: &mut self.json,
+        })
+    }
+}
+
+/// Renders a log record as a single line of JSON, with key-value pairs
+/// of the record becoming members of the JSON object
+fn format_json(record: &Record, timestamp: Option<&str>) -> String {
+    let mut writer = JsonObjectWriter {
+        json: String::from("{"),
+    };
+    if let Some(timestamp) = timestamp {
+        writer.json.push_str("\"timestamp\":");
+        write_json_string(&mut writer.json, timestamp);
+        writer.json.push(',');
+    }
+    writer.json.push_str("\"level\":");
+    write_json_string(
+        &mut writer.json,
+        record.level().as_str().to_lowercase().as_str(),
+    );
+    let _ = record.key_values().visit(&mut writer);
+    writer.json.push_str(",\"message\":");
+    write_json_string(&mut writer.json, record.args().to_string().as_str());
+    writer.json.push('}');
+    writer.json
+}
+
+pub(crate) const DEFAULT_LINE_TEMPLATE: &str = "[{icon}] {message}";
+
+const LINE_TEMPLATE_PLACEHOLDERS: [&str; 6] = [
+    "timestamp",
+    "level",
+    "icon",
+    "service",
+    "elapsed",
+    "message",
+];
+
+/// Splits a template like "[{icon}] {message}" into literal text
+/// and placeholder names, alternating
+fn split_line_template(template: &str) -> Result<Vec<&str>, String> {
+    let mut parts: Vec<&str> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else {
+            return Err(String::from("has an unclosed brace \"{\"."));
+        };
+        let placeholder = &rest[start + 1..start + length];
+        if !LINE_TEMPLATE_PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!(
+                "has unknown placeholder \"{{{placeholder}}}\", supported are: {}.",
+                LINE_TEMPLATE_PLACEHOLDERS
+                    .map(|e| format!("{{{e}}}"))
+                    .join(", ")
+            ));
+        }
+        parts.push(&rest[..start]);
+        parts.push(placeholder);
+        rest = &rest[start + length + 1..];
+    }
+    parts.push(rest);
+    Ok(parts)
+}
+
+pub(crate) fn parse_line_template(template: &str) -> Result<String, String> {
+    split_line_template(template).map(|_| template.to_string())
+}
+
+fn format_text(template: &str, icon: char, record: &Record, timestamp: Option<&str>) -> String {
+    let text_of = |key: &str| {
+        record
+            .key_values()
+            .get(key.into())
+            .map(|value| value.to_string())
+            .unwrap_or_default()
+    };
+
+    let mut line = String::new();
+    let parts = split_line_template(template).unwrap_or_default();
+    for (index, part) in parts.iter().enumerate() {
+        if index % 2 == 0 {
+            line.push_str(part);
+            continue;
+        }
+        match *part {
+            "timestamp" => line.push_str(timestamp.unwrap_or_default()),
+            "level" => line.push_str(record.level().as_str()),
+            "icon" => line.push(icon),
+            "message" => {
+                let _ = write!(line, "{}", record.args());
+            }
+            key => line.push_str(text_of(key).as_str()),
+        }
+    }
+    line
 }
 
 impl Log for CustomLog {
@@ -67,52 +295,109 @@ impl Log for CustomLog {
     fn log(&self, record: &Record) {
         let thread_id = thread::current().id();
         {
-            let included_threads = unsafe { INCLUDED_THREADS.lock() }.expect("poisoned lock");
+            let included_threads = INCLUDED_THREADS.lock().expect("poisoned lock");
             if !included_threads.contains(&thread_id) {
                 return;
             }
         }
 
-        let value: Value = record
-            .key_values()
-            .get("sublevel".into())
-            .unwrap_or(Value::from(SubLevel::Failed as u64));
-        let sublevel = SubLevel::from(value.to_u64().expect("malformed sublevel"));
+        {
+            let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+            let LogConfig {
+                stdout, dashboard, ..
+            } = &mut *log_config;
+            if let Some(dashboard) = dashboard.as_mut() {
+                if dashboard.update(record) {
+                    write_dashboard(dashboard, stdout);
+                    return;
+                }
+                dashboard.detach();
+            }
+
+            if record.level() > log_config.max_log_level {
+                return;
+            }
+
+            #[cfg(unix)]
+            if let Some(datagram_sink) = log_config.datagram_sink.as_ref() {
+                let _ = datagram_sink.send(record);
+                return;
+            }
+        }
+
+        let sublevel = match record.key_values().get("sublevel".into()) {
+            Some(value) => SubLevel::from(value.to_u64().expect("malformed sublevel")),
+            None => SubLevel::from(record.level()),
+        };
 
         let icon = match sublevel {
             SubLevel::Starting => '*',
             SubLevel::Succeeded => '+',
             SubLevel::Failed => '-',
+            SubLevel::Debug => '#',
+            SubLevel::Trace => '.',
         };
 
-        let mut log_config = unsafe { LOG_CONFIG.lock() }.expect("poisoned lock");
-
-        let target: &mut Option<Arc<Mutex<&'static mut dyn RawStream>>> = match sublevel {
-            SubLevel::Starting | SubLevel::Succeeded => &mut log_config.stdout,
-            SubLevel::Failed => &mut log_config.stderr,
+        let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+        let output_format = log_config.output_format;
+        let timestamp = log_config.timestamp_format.map(|timestamp_format| {
+            format_timestamp(
+                timestamp_format,
+                log_config.started.unwrap_or_else(Instant::now),
+            )
+        });
+        let line = match output_format {
+            OutputFormat::Text => match log_config.line_template.as_deref() {
+                Some(template) => format_text(template, icon, record, timestamp.as_deref()),
+                None => format!("[{}] {}", icon, record.args()),
+            },
+            OutputFormat::Json => format_json(record, timestamp.as_deref()),
         };
 
+        // NOTE: JSON Lines go to stdout exclusively so that consumers
+        //       only need to read a single stream
+        let target: &mut Option<Arc<Mutex<&'static mut (dyn RawStream + Send)>>> =
+            match (output_format, sublevel) {
+                _ if log_config.log_target == LogTarget::Stderr => &mut log_config.stderr,
+                (OutputFormat::Json, _) => &mut log_config.stdout,
+                (_, SubLevel::Starting | SubLevel::Succeeded) => &mut log_config.stdout,
+                (_, SubLevel::Failed | SubLevel::Debug | SubLevel::Trace) => &mut log_config.stderr,
+            };
+
         if let Some(target) = target.as_mut() {
             let mut target = target.lock().expect("poisoned lock");
-            let target: &mut dyn RawStream = *target;
+            let target: &mut (dyn RawStream + Send) = *target;
 
-            let _ = writeln!(target, "[{}] {}", icon, record.args());
+            let _ = writeln!(target, "{line}");
         }
     }
 
     fn flush(&self) {}
 }
 
+fn write_dashboard(
+    dashboard: &mut Dashboard,
+    stdout: &mut Option<Arc<Mutex<&'static mut (dyn RawStream + Send)>>>,
+) {
+    if let Some(target) = stdout.as_mut() {
+        let mut target = target.lock().expect("poisoned lock");
+        let target: &mut (dyn RawStream + Send) = *target;
+
+        let _ = write!(target, "{}", dashboard.render(Instant::now()));
+        let _ = target.flush();
+    }
+}
+
 pub(crate) fn with_exclusive_logging<F, R>(
     max_log_level: LevelFilter,
-    stdout: Arc<Mutex<&'static mut dyn RawStream>>,
-    stderr: Arc<Mutex<&'static mut dyn RawStream>>,
+    stdout: Arc<Mutex<&'static mut (dyn RawStream + Send)>>,
+    stderr: Arc<Mutex<&'static mut (dyn RawStream + Send)>>,
     inner_function: F,
 ) -> R
 where
     F: FnOnce() -> R,
 {
-    let _locked = unsafe { LOG_ACTIVE.lock() }.expect("poisoned lock");
+    let _locked = LOG_ACTIVE.lock().expect("poisoned lock");
 
     // NOTE: set_logger only ever succeeds *once* per process lifetime
     if let Err(error) = set_logger(&CUSTOM_LOG) {
@@ -125,37 +410,153 @@ where
 
     set_max_level(max_log_level);
     {
-        let mut log_config = unsafe { LOG_CONFIG.lock() }.expect("poisoned lock");
+        let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
         log_config.stdout = Some(stdout);
         log_config.stderr = Some(stderr);
+        log_config.max_log_level = max_log_level;
+        log_config.started = Some(Instant::now());
     }
 
     let res = with_logging_for_current_thread(inner_function);
 
     set_max_level(LevelFilter::Off);
     {
-        let mut log_config = unsafe { LOG_CONFIG.lock() }.expect("poisoned lock");
+        let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
         log_config.stdout = None;
         log_config.stderr = None;
+        log_config.output_format = OutputFormat::Text;
+        log_config.dashboard = None;
+        log_config.max_log_level = LevelFilter::Off;
+        log_config.timestamp_format = None;
+        log_config.line_template = None;
+        log_config.started = None;
+        log_config.log_target = LogTarget::Console;
+        #[cfg(unix)]
+        {
+            log_config.datagram_sink = None;
+        }
     }
 
     res
 }
 
+/// Sends all further records to the given target, with journald
+/// and syslog connecting to the usual socket paths
+pub(crate) fn set_log_target(log_target: LogTarget) -> Result<(), std::io::Error> {
+    match log_target {
+        #[cfg(unix)]
+        LogTarget::Journald => connect_log_target(log_target, Path::new(JOURNALD_SOCKET_PATH)),
+        #[cfg(unix)]
+        LogTarget::Syslog => connect_log_target(log_target, Path::new(SYSLOG_SOCKET_PATH)),
+        _ => {
+            let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+            log_config.log_target = log_target;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn connect_log_target(
+    log_target: LogTarget,
+    socket_path: &Path,
+) -> Result<(), std::io::Error> {
+    let datagram_sink = DatagramSink::connect(log_target, socket_path)?;
+    let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    log_config.log_target = log_target;
+    log_config.datagram_sink = Some(datagram_sink);
+    Ok(())
+}
+
+pub(crate) fn set_log_level(max_log_level: LevelFilter) {
+    let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    log_config.max_log_level = max_log_level;
+    set_max_level(max_log_level);
+}
+
+pub(crate) fn stdout_is_terminal() -> bool {
+    let log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    match log_config.stdout.as_ref() {
+        Some(stdout) => stdout.lock().expect("poisoned lock").is_terminal(),
+        None => false,
+    }
+}
+
+/// Replaces line-based status output by a live table of the given services
+/// until `stop_dashboard` is called
+pub(crate) fn start_dashboard<S: AsRef<str>>(services: &[S]) {
+    let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    let LogConfig {
+        stdout, dashboard, ..
+    } = &mut *log_config;
+    let mut new_dashboard = Dashboard::new(services);
+    write_dashboard(&mut new_dashboard, stdout);
+    *dashboard = Some(new_dashboard);
+
+    // NOTE: The dashboard needs to see per-attempt debug records
+    set_max_level(log::max_level().max(LevelFilter::Debug));
+}
+
+/// Re-draws the dashboard so that elapsed times keep moving
+/// in between status records
+pub(crate) fn refresh_dashboard() {
+    let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    let LogConfig {
+        stdout, dashboard, ..
+    } = &mut *log_config;
+    if let Some(dashboard) = dashboard.as_mut() {
+        write_dashboard(dashboard, stdout);
+    }
+}
+
+/// Draws the dashboard one last time followed by any messages it had to cut short,
+/// and goes back to line-based status output
+pub(crate) fn stop_dashboard() {
+    refresh_dashboard();
+    let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    if let (Some(dashboard), Some(target)) = (log_config.dashboard.take(), &log_config.stdout) {
+        let mut target = target.lock().expect("poisoned lock");
+        let _ = write!(target, "{}", dashboard.render_truncated_messages());
+        let _ = target.flush();
+    }
+    set_max_level(log_config.max_log_level);
+}
+
+pub(crate) fn set_output_format(output_format: OutputFormat) {
+    let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    log_config.output_format = output_format;
+}
+
+/// Configures text lines to follow the given template, see
+/// `LINE_TEMPLATE_PLACEHOLDERS`, and JSON objects to carry a timestamp
+pub(crate) fn set_line_format(
+    timestamp_format: Option<TimestampFormat>,
+    line_template: Option<String>,
+) {
+    let mut log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    log_config.timestamp_format = timestamp_format;
+    log_config.line_template = line_template;
+}
+
+pub(crate) fn get_output_format() -> OutputFormat {
+    let log_config = LOG_CONFIG.lock().expect("poisoned lock");
+    log_config.output_format
+}
+
 pub(crate) fn with_logging_for_current_thread<F, R>(inner_function: F) -> R
 where
     F: FnOnce() -> R,
 {
     let thread_id = thread::current().id();
     {
-        let mut included_threads = unsafe { INCLUDED_THREADS.lock() }.expect("poisoned lock");
+        let mut included_threads = INCLUDED_THREADS.lock().expect("poisoned lock");
         included_threads.insert(thread_id);
     }
 
     let ret = inner_function();
 
     {
-        let mut included_threads = unsafe { INCLUDED_THREADS.lock() }.expect("poisoned lock");
+        let mut included_threads = INCLUDED_THREADS.lock().expect("poisoned lock");
         included_threads.remove(&thread_id);
     }
 
@@ -168,16 +569,28 @@ mod tests {
     use extend_lifetime::extend_lifetime;
     use indoc::indoc;
     use log::kv::ToValue;
-    use log::LevelFilter;
-    use log::{error, info};
+    use log::kv::Value;
+    use log::{debug, error, info, trace};
+    use log::{Level, LevelFilter, Record};
 
     use std::sync::Arc;
     use std::sync::Mutex;
     use std::thread;
 
+    #[cfg(unix)]
+    use super::connect_log_target;
+    use super::format_text;
+    use super::parse_line_template;
+    use super::set_log_level;
+    use super::set_log_target;
+    use super::set_output_format;
     use super::with_exclusive_logging;
     use super::with_logging_for_current_thread;
+    use super::write_json_string;
+    use super::LogTarget;
+    use super::OutputFormat;
     use super::SubLevel;
+    use super::DEFAULT_LINE_TEMPLATE;
     use super::INCLUDED_THREADS;
 
     #[test]
@@ -185,20 +598,30 @@ mod tests {
         assert_eq!(SubLevel::Starting.to_value().to_u64().unwrap(), 0);
         assert_eq!(SubLevel::Succeeded.to_value().to_u64().unwrap(), 1);
         assert_eq!(SubLevel::Failed.to_value().to_u64().unwrap(), 2);
+        assert_eq!(SubLevel::Debug.to_value().to_u64().unwrap(), 3);
+        assert_eq!(SubLevel::Trace.to_value().to_u64().unwrap(), 4);
 
         assert_eq!(SubLevel::from(0), SubLevel::Starting);
         assert_eq!(SubLevel::from(1), SubLevel::Succeeded);
         assert_eq!(SubLevel::from(2), SubLevel::Failed);
-        assert_eq!(SubLevel::from(3), SubLevel::Failed);
+        assert_eq!(SubLevel::from(3), SubLevel::Debug);
+        assert_eq!(SubLevel::from(4), SubLevel::Trace);
+        assert_eq!(SubLevel::from(5), SubLevel::Failed);
+
+        assert_eq!(SubLevel::from(Level::Error), SubLevel::Failed);
+        assert_eq!(SubLevel::from(Level::Warn), SubLevel::Failed);
+        assert_eq!(SubLevel::from(Level::Info), SubLevel::Failed);
+        assert_eq!(SubLevel::from(Level::Debug), SubLevel::Debug);
+        assert_eq!(SubLevel::from(Level::Trace), SubLevel::Trace);
     }
 
     #[test]
     fn test_with_exclusive_logging() {
         let mut stdout_buffer = Vec::<u8>::new();
         let mut stderr_buffer = Vec::<u8>::new();
-        let stdout: Arc<Mutex<&mut dyn RawStream>> =
+        let stdout: Arc<Mutex<&mut (dyn RawStream + Send)>> =
             Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stdout_buffer) }));
-        let stderr: Arc<Mutex<&mut dyn RawStream>> =
+        let stderr: Arc<Mutex<&mut (dyn RawStream + Send)>> =
             Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stderr_buffer) }));
 
         let expected_result = 123;
@@ -207,6 +630,10 @@ mod tests {
             info!(target: module_path!(), sublevel = SubLevel::Succeeded; "22222 22222");
             error!("33333 33333");
             error!("44444 44444");
+            debug!("55555 55555");
+            set_log_level(LevelFilter::Trace);
+            debug!("66666 66666");
+            trace!("77777 77777");
             expected_result
         });
 
@@ -225,30 +652,174 @@ mod tests {
             indoc! {"
                 [-] 33333 33333
                 [-] 44444 44444
+                [#] 66666 66666
+                [.] 77777 77777
             "}
         );
 
         assert_eq!(actual_result, expected_result);
     }
 
+    #[test]
+    fn test_write_json_string() {
+        let mut json = String::new();
+        write_json_string(&mut json, "a\"b\\c\nd\u{1}e\u{e4}");
+        assert_eq!(json, "\"a\\\"b\\\\c\\nd\\u0001e\u{e4}\"");
+    }
+
+    #[test]
+    fn test_with_exclusive_logging_for_json() {
+        let mut stdout_buffer = Vec::<u8>::new();
+        let mut stderr_buffer = Vec::<u8>::new();
+        let stdout: Arc<Mutex<&mut (dyn RawStream + Send)>> =
+            Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stdout_buffer) }));
+        let stderr: Arc<Mutex<&mut (dyn RawStream + Send)>> =
+            Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stderr_buffer) }));
+
+        with_exclusive_logging(LevelFilter::Info, stdout, stderr, || {
+            set_output_format(OutputFormat::Json);
+            info!(target: module_path!(), sublevel = SubLevel::Starting, event = "waiting",
+                service = "h:1", timeout = 2; "11111 \"11111\"");
+            error!(event = "timed_out", address = None::<&str>, elapsed = 1.5,
+                done = true; "22222 22222");
+        });
+
+        let stdout = String::from_utf8(stdout_buffer).expect("UTF-8 decode error");
+        let stderr = String::from_utf8(stderr_buffer).expect("UTF-8 decode error");
+
+        assert_eq!(
+            stdout,
+            indoc! {r#"
+                {"level":"info","event":"waiting","service":"h:1","timeout":2,"message":"11111 \"11111\""}
+                {"level":"error","event":"timed_out","address":null,"elapsed":1.5,"done":true,"message":"22222 22222"}
+            "#}
+        );
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_parse_line_template() {
+        assert_eq!(
+            parse_line_template("{timestamp} [{icon}] {message}"),
+            Ok(String::from("{timestamp} [{icon}] {message}"))
+        );
+        assert_eq!(parse_line_template("plain"), Ok(String::from("plain")));
+        assert_eq!(
+            parse_line_template("{message"),
+            Err(String::from("has an unclosed brace \"{\"."))
+        );
+        assert_eq!(
+            parse_line_template("{icon} {nope}"),
+            Err(String::from(
+                "has unknown placeholder \"{nope}\", supported are: {timestamp}, {level}, {icon}, {service}, {elapsed}, {message}."
+            ))
+        );
+    }
+
+    #[test]
+    fn test_format_text() {
+        let key_values = [
+            ("service", Value::from("h:1")),
+            ("elapsed", Value::from(1.5)),
+        ];
+        let record = Record::builder()
+            .level(Level::Info)
+            .args(format_args!("hello"))
+            .key_values(&key_values)
+            .build();
+
+        assert_eq!(
+            format_text(DEFAULT_LINE_TEMPLATE, '+', &record, None),
+            "[+] hello"
+        );
+        assert_eq!(
+            format_text(
+                "{timestamp} {level} {icon} {service} {elapsed} {message}!",
+                '*',
+                &record,
+                Some("T")
+            ),
+            "T INFO * h:1 1.5 hello!"
+        );
+
+        let record = Record::builder().args(format_args!("bye")).build();
+        assert_eq!(
+            format_text("<{service}> {message}", '-', &record, None),
+            "<> bye"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_with_exclusive_logging_for_syslog() {
+        use std::os::unix::net::UnixDatagram;
+
+        let mut stdout_buffer = Vec::<u8>::new();
+        let mut stderr_buffer = Vec::<u8>::new();
+        let stdout: Arc<Mutex<&mut (dyn RawStream + Send)>> =
+            Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stdout_buffer) }));
+        let stderr: Arc<Mutex<&mut (dyn RawStream + Send)>> =
+            Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stderr_buffer) }));
+        let path = std::env::temp_dir().join(format!(
+            "rust-for-it-test-logging-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        with_exclusive_logging(LevelFilter::Info, stdout, stderr, || {
+            connect_log_target(LogTarget::Syslog, &path).unwrap();
+            error!("11111 11111");
+        });
+        let _ = std::fs::remove_file(&path);
+
+        let mut buffer = [0u8; 1024];
+        let length = server.recv(&mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..length],
+            format!("<11>rust-for-it[{}]: 11111 11111", std::process::id()).as_bytes()
+        );
+        assert!(stdout_buffer.is_empty());
+        assert!(stderr_buffer.is_empty());
+    }
+
+    #[test]
+    fn test_with_exclusive_logging_for_stderr() {
+        let mut stdout_buffer = Vec::<u8>::new();
+        let mut stderr_buffer = Vec::<u8>::new();
+        let stdout: Arc<Mutex<&mut (dyn RawStream + Send)>> =
+            Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stdout_buffer) }));
+        let stderr: Arc<Mutex<&mut (dyn RawStream + Send)>> =
+            Arc::new(Mutex::new(unsafe { extend_lifetime(&mut stderr_buffer) }));
+
+        with_exclusive_logging(LevelFilter::Info, stdout, stderr, || {
+            set_log_target(LogTarget::Stderr).unwrap();
+            info!(target: module_path!(), sublevel = SubLevel::Starting; "11111 11111");
+            error!("22222 22222");
+        });
+
+        assert!(stdout_buffer.is_empty());
+        assert_eq!(
+            String::from_utf8(stderr_buffer).expect("UTF-8 decode error"),
+            indoc! {"
+                [*] 11111 11111
+                [-] 22222 22222
+            "}
+        );
+    }
+
     #[test]
     fn test_with_logging_for_current_thread() {
         let thread_id = thread::current().id();
-        assert!(!unsafe { INCLUDED_THREADS.lock() }
-            .unwrap()
-            .contains(&thread_id));
+        assert!(!INCLUDED_THREADS.lock().unwrap().contains(&thread_id));
 
         let expected_result = 456;
         let actual_result = with_logging_for_current_thread(|| {
-            assert!(unsafe { INCLUDED_THREADS.lock() }
-                .unwrap()
-                .contains(&thread_id));
+            assert!(INCLUDED_THREADS.lock().unwrap().contains(&thread_id));
             expected_result
         });
 
-        assert!(!unsafe { INCLUDED_THREADS.lock() }
-            .unwrap()
-            .contains(&thread_id));
+        assert!(!INCLUDED_THREADS.lock().unwrap().contains(&thread_id));
 
         assert_eq!(actual_result, expected_result);
     }
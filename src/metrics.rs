@@ -0,0 +1,146 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use rust_for_it::WaitReport;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+type Gauge = (&'static str, &'static str, fn(&WaitReport) -> String);
+
+fn escape_label_value(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders per-service gauges in the OpenMetrics text format,
+/// as understood by the textfile collector of node_exporter
+pub(crate) fn render_openmetrics_report(reports: &[WaitReport]) -> String {
+    let gauges: [Gauge; 3] = [
+        (
+            "rust_for_it_wait_duration_seconds",
+            "Seconds spent waiting for the service",
+            |report| format!("{:.3}", report.elapsed.as_secs_f64()),
+        ),
+        (
+            "rust_for_it_wait_attempts",
+            "Number of connection attempts made to the service",
+            |report| report.attempts.to_string(),
+        ),
+        (
+            "rust_for_it_wait_success",
+            "Whether the service was found available (1) or not (0)",
+            |report| String::from(if report.result.is_ok() { "1" } else { "0" }),
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, help, value_of) in gauges {
+        let _ = writeln!(text, "# TYPE {name} gauge");
+        let _ = writeln!(text, "# HELP {name} {help}.");
+        for report in reports {
+            let service = escape_label_value(&report.service);
+            let _ = writeln!(text, "{name}{{service=\"{service}\"}} {}", value_of(report));
+        }
+    }
+    text.push_str("# EOF\n");
+    text
+}
+
+/// Writes to a temporary sibling first so that collectors
+/// never pick up a half-written file
+pub(crate) fn write_openmetrics_report(
+    path: &Path,
+    reports: &[WaitReport],
+) -> Result<(), std::io::Error> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&temporary_path, render_openmetrics_report(reports))?;
+    fs::rename(&temporary_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary_path);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use rust_for_it::WaitReport;
+
+    use std::io;
+    use std::time::Duration;
+
+    use super::escape_label_value;
+    use super::render_openmetrics_report;
+    use super::write_openmetrics_report;
+
+    fn example_reports() -> Vec<WaitReport> {
+        let mut good = WaitReport::new("good:1");
+        good.elapsed = Duration::from_millis(100);
+        good.attempts = 1;
+
+        let mut bad = WaitReport::new("bad:2");
+        bad.elapsed = Duration::from_millis(2500);
+        bad.attempts = 5;
+        bad.result = Err(io::Error::new(io::ErrorKind::TimedOut, "Time is up"));
+
+        vec![good, bad]
+    }
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn test_render_openmetrics_report() {
+        assert_eq!(
+            render_openmetrics_report(&example_reports()),
+            indoc! {r#"
+                # TYPE rust_for_it_wait_duration_seconds gauge
+                # HELP rust_for_it_wait_duration_seconds Seconds spent waiting for the service.
+                rust_for_it_wait_duration_seconds{service="good:1"} 0.100
+                rust_for_it_wait_duration_seconds{service="bad:2"} 2.500
+                # TYPE rust_for_it_wait_attempts gauge
+                # HELP rust_for_it_wait_attempts Number of connection attempts made to the service.
+                rust_for_it_wait_attempts{service="good:1"} 1
+                rust_for_it_wait_attempts{service="bad:2"} 5
+                # TYPE rust_for_it_wait_success gauge
+                # HELP rust_for_it_wait_success Whether the service was found available (1) or not (0).
+                rust_for_it_wait_success{service="good:1"} 1
+                rust_for_it_wait_success{service="bad:2"} 0
+                # EOF
+            "#}
+        );
+    }
+
+    #[test]
+    fn test_write_openmetrics_report() {
+        let path = std::env::temp_dir().join(format!(
+            "rust-for-it-test-metrics-{}.prom",
+            std::process::id()
+        ));
+        let reports = example_reports();
+
+        write_openmetrics_report(&path, &reports).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(content, render_openmetrics_report(&reports));
+        assert!(write_openmetrics_report(
+            &std::env::temp_dir().join("no-such-directory/metrics.prom"),
+            &reports
+        )
+        .is_err());
+    }
+}
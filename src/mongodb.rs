@@ -0,0 +1,404 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::network::{connect, resolve_host_with_default_port};
+use crate::probe::{Probe, ProbeOutcome};
+use crate::util::{invalid_input, protocol_error, take, take_i32_le};
+
+const DEFAULT_PORT: u16 = 27017;
+
+const OP_MSG: i32 = 2013;
+
+/// Upper bound for replies accepted from the server
+const MAX_MESSAGE_SIZE: usize = 1_048_576;
+
+/// Error code of servers older than MongoDB 4.4.2 for command "hello"
+const COMMAND_NOT_FOUND: i32 = 59;
+
+/// Checks that a MongoDB server answers command "hello" with "ok: 1",
+/// for services like "mongodb://localhost:27017", or with option "?primary"
+/// that the server is a writable primary
+pub(crate) struct MongoProbe {
+    addresses: Vec<SocketAddr>,
+    require_primary: bool,
+}
+
+/// Top-level field values of a BSON document that matter here
+#[derive(Debug, PartialEq)]
+enum Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Other,
+}
+
+fn take_cstring(input: &mut &[u8]) -> Result<String, io::Error> {
+    let end = input
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| protocol_error(String::from("Unterminated BSON string")))?;
+    let text = String::from_utf8_lossy(&input[..end]).to_string();
+    *input = &input[end + 1..];
+    Ok(text)
+}
+
+/// BSON document for a command like `{hello: 1, $db: "admin"}`
+fn encode_command(command: &str) -> Vec<u8> {
+    let mut elements = vec![0x10]; // int32
+    elements.extend_from_slice(command.as_bytes());
+    elements.push(0);
+    elements.extend_from_slice(&1i32.to_le_bytes());
+    elements.push(0x02); // string
+    elements.extend_from_slice(b"$db\0");
+    elements.extend_from_slice(&6i32.to_le_bytes());
+    elements.extend_from_slice(b"admin\0");
+
+    let mut document = (elements.len() as i32 + 5).to_le_bytes().to_vec();
+    document.extend_from_slice(&elements);
+    document.push(0);
+    document
+}
+
+/// Decodes the top-level fields of a BSON document, skipping nested ones
+fn decode_document(document: &[u8]) -> Result<Vec<(String, Value)>, io::Error> {
+    let mut input = document;
+    let length = take_i32_le(&mut input)?;
+    if length < 5 || length as usize > document.len() {
+        return Err(protocol_error(String::from("Malformed BSON document")));
+    }
+    let mut input = &document[4..length as usize - 1];
+    let mut fields = Vec::new();
+    while !input.is_empty() {
+        let kind = take(&mut input, 1)?[0];
+        let name = take_cstring(&mut input)?;
+        let value = match kind {
+            0x01 => {
+                let bytes = take(&mut input, 8)?;
+                Value::Number(f64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            0x02 => {
+                let length = take_i32_le(&mut input)?.max(1) as usize;
+                let bytes = take(&mut input, length)?;
+                Value::String(String::from_utf8_lossy(&bytes[..length - 1]).to_string())
+            }
+            0x03 | 0x04 => {
+                let length = take_i32_le(&mut input)?.max(4) as usize;
+                take(&mut input, length - 4)?;
+                Value::Other
+            }
+            0x05 => {
+                let length = take_i32_le(&mut input)?.max(0) as usize;
+                take(&mut input, length + 1)?;
+                Value::Other
+            }
+            0x07 => take(&mut input, 12).map(|_| Value::Other)?,
+            0x08 => Value::Boolean(take(&mut input, 1)?[0] != 0),
+            0x09 | 0x11 => take(&mut input, 8).map(|_| Value::Other)?,
+            0x0a | 0x7f | 0xff => Value::Other,
+            0x0b => {
+                take_cstring(&mut input)?;
+                take_cstring(&mut input)?;
+                Value::Other
+            }
+            0x10 => Value::Number(f64::from(take_i32_le(&mut input)?)),
+            0x12 => {
+                let bytes = take(&mut input, 8)?;
+                Value::Number(i64::from_le_bytes(bytes.try_into().unwrap()) as f64)
+            }
+            0x13 => take(&mut input, 16).map(|_| Value::Other)?,
+            _ => {
+                return Err(protocol_error(format!(
+                    "Unsupported BSON type {kind:#04x} for field \"{name}\""
+                )))
+            }
+        };
+        fields.push((name, value));
+    }
+    Ok(fields)
+}
+
+fn field<'a>(fields: &'a [(String, Value)], name: &str) -> Option<&'a Value> {
+    fields
+        .iter()
+        .find(|(field_name, _)| field_name == name)
+        .map(|(_, value)| value)
+}
+
+fn is_true(value: Option<&Value>) -> bool {
+    matches!(value, Some(Value::Boolean(true)))
+        || matches!(value, Some(Value::Number(n)) if *n == 1.0)
+}
+
+/// Sends a command via OP_MSG and returns the fields of the reply
+fn run_command(
+    stream: &mut TcpStream,
+    request_id: i32,
+    command: &str,
+) -> io::Result<Vec<(String, Value)>> {
+    let document = encode_command(command);
+    let mut message = Vec::new();
+    message.extend_from_slice(&(16 + 4 + 1 + document.len() as i32).to_le_bytes());
+    message.extend_from_slice(&request_id.to_le_bytes());
+    message.extend_from_slice(&0i32.to_le_bytes());
+    message.extend_from_slice(&OP_MSG.to_le_bytes());
+    message.extend_from_slice(&0u32.to_le_bytes()); // flags
+    message.push(0); // section of kind "body"
+    message.extend_from_slice(&document);
+    stream.write_all(&message)?;
+
+    let mut header = [0u8; 16];
+    stream.read_exact(&mut header)?;
+    let mut input = &header[..];
+    let length = take_i32_le(&mut input)?.max(16) as usize;
+    take_i32_le(&mut input)?;
+    let response_to = take_i32_le(&mut input)?;
+    let op_code = take_i32_le(&mut input)?;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(protocol_error(format!(
+            "Reply of {length} bytes is too large"
+        )));
+    }
+    let mut body = vec![0u8; length - 16];
+    stream.read_exact(&mut body)?;
+    if op_code != OP_MSG || response_to != request_id {
+        return Err(protocol_error(String::from(
+            "Reply does not match the command",
+        )));
+    }
+    match body.get(4..) {
+        Some([0, document @ ..]) => decode_document(document),
+        _ => Err(protocol_error(String::from("Reply lacks a body section"))),
+    }
+}
+
+impl MongoProbe {
+    /// Resolves "mongodb://host[:port][/][?primary]" once
+    pub(crate) fn create(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let spec = service.split_once("://").map_or("", |(_, spec)| spec);
+        let (address, option) = spec.split_once('?').unwrap_or((spec, ""));
+        let require_primary = match option {
+            "" => false,
+            "primary" => true,
+            _ => return Err(invalid_input(format!("Unknown option \"{option}\""))),
+        };
+        let host_and_port = address.strip_suffix('/').unwrap_or(address);
+        if host_and_port.is_empty() {
+            return Err(invalid_input(String::from("No host given")));
+        }
+        Ok(Box::new(MongoProbe {
+            addresses: resolve_host_with_default_port(host_and_port, DEFAULT_PORT)?,
+            require_primary,
+        }))
+    }
+
+    fn check(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let mut stream = connect(&self.addresses[0], timeout)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+
+        let mut reply = run_command(&mut stream, 1, "hello")?;
+        let mut primary_field = "isWritablePrimary";
+        if matches!(field(&reply, "code"), Some(Value::Number(code)) if *code == f64::from(COMMAND_NOT_FOUND))
+        {
+            reply = run_command(&mut stream, 2, "isMaster")?;
+            primary_field = "ismaster";
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+
+        if !is_true(field(&reply, "ok")) {
+            let error_message = match field(&reply, "errmsg") {
+                Some(Value::String(error_message)) => error_message.as_str(),
+                _ => "unknown error",
+            };
+            return Err(io::Error::other(format!("Command failed: {error_message}")));
+        }
+        if self.require_primary && !is_true(field(&reply, primary_field)) {
+            let text = match field(&reply, "primary") {
+                Some(Value::String(primary)) => {
+                    format!("Not a writable primary, {primary} is")
+                }
+                _ => String::from("Not a writable primary"),
+            };
+            return Err(io::Error::other(text));
+        }
+        Ok(())
+    }
+}
+
+impl Probe for MongoProbe {
+    fn activity(&self) -> String {
+        let command = if self.require_primary {
+            "find a writable primary"
+        } else {
+            "say hello to MongoDB"
+        };
+        format!("{command} at {}", self.addresses[0])
+    }
+
+    fn attempt(&self, timeout: Option<Duration>) -> ProbeOutcome {
+        match self.check(timeout) {
+            Ok(()) => ProbeOutcome::Ready,
+            Err(error) => ProbeOutcome::NotYet(error),
+        }
+    }
+
+    fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    use crate::probe::ProbeOutcome;
+
+    use super::decode_document;
+    use super::encode_command;
+    use super::MongoProbe;
+    use super::Value;
+    use super::OP_MSG;
+
+    fn encode_reply(fields: &[(&str, Value)]) -> Vec<u8> {
+        let mut elements = Vec::new();
+        for (name, value) in fields {
+            let (kind, bytes) = match value {
+                Value::Number(number) => (0x01, number.to_le_bytes().to_vec()),
+                Value::Boolean(boolean) => (0x08, vec![*boolean as u8]),
+                Value::String(text) => {
+                    let mut bytes = (text.len() as i32 + 1).to_le_bytes().to_vec();
+                    bytes.extend_from_slice(text.as_bytes());
+                    bytes.push(0);
+                    (0x02, bytes)
+                }
+                Value::Other => (0x0a, Vec::new()),
+            };
+            elements.push(kind);
+            elements.extend_from_slice(name.as_bytes());
+            elements.push(0);
+            elements.extend_from_slice(&bytes);
+        }
+        let mut document = (elements.len() as i32 + 5).to_le_bytes().to_vec();
+        document.extend_from_slice(&elements);
+        document.push(0);
+        document
+    }
+
+    /// Plays a primary, a secondary or a server too old for command "hello"
+    fn serve_mongodb(stream: &mut TcpStream, role: &str) -> io::Result<()> {
+        loop {
+            let mut header = [0u8; 16];
+            stream.read_exact(&mut header)?;
+            let length = i32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let mut body = vec![0u8; length - 16];
+            stream.read_exact(&mut body)?;
+            let fields = decode_document(&body[5..]).unwrap();
+            let command = fields[0].0.as_str();
+
+            let reply = match (role, command) {
+                ("legacy", "hello") => encode_reply(&[
+                    ("ok", Value::Number(0.0)),
+                    (
+                        "errmsg",
+                        Value::String(String::from("no such command: 'hello'")),
+                    ),
+                    ("code", Value::Number(59.0)),
+                ]),
+                (_, "hello" | "isMaster") => encode_reply(&[
+                    ("isWritablePrimary", Value::Boolean(role == "primary")),
+                    ("ismaster", Value::Boolean(role != "secondary")),
+                    ("primary", Value::String(String::from("db-1:27017"))),
+                    ("ok", Value::Number(1.0)),
+                ]),
+                _ => panic!("Unexpected command {command}"),
+            };
+            let mut message = Vec::new();
+            message.extend_from_slice(&(16 + 5 + reply.len() as i32).to_le_bytes());
+            message.extend_from_slice(&0i32.to_le_bytes());
+            message.extend_from_slice(&header[4..8]);
+            message.extend_from_slice(&OP_MSG.to_le_bytes());
+            message.extend_from_slice(&[0, 0, 0, 0, 0]);
+            message.extend_from_slice(&reply);
+            stream.write_all(&message)?;
+        }
+    }
+
+    fn start_mongodb_server(role: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = serve_mongodb(&mut stream, role);
+            }
+        });
+        port
+    }
+
+    fn attempt(service: &str) -> ProbeOutcome {
+        MongoProbe::create(service)
+            .unwrap()
+            .attempt(Some(Duration::from_secs(5)))
+    }
+
+    #[test]
+    fn test_encode_and_decode_document() {
+        assert_eq!(
+            decode_document(&encode_command("hello")).unwrap(),
+            [
+                (String::from("hello"), Value::Number(1.0)),
+                (String::from("$db"), Value::String(String::from("admin"))),
+            ]
+        );
+        assert!(decode_document(&[5, 0, 0, 0]).is_err());
+        assert!(decode_document(&[8, 0, 0, 0, 0x08, b'x', 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_mongodb_probe() {
+        for role in ["primary", "legacy"] {
+            let port = start_mongodb_server(role);
+            assert!(matches!(
+                attempt(&format!("mongodb://127.0.0.1:{port}/?primary")),
+                ProbeOutcome::Ready
+            ));
+        }
+
+        let port = start_mongodb_server("secondary");
+        let probe = MongoProbe::create(&format!("mongodb://127.0.0.1:{port}")).unwrap();
+        assert_eq!(
+            probe.activity(),
+            format!("say hello to MongoDB at 127.0.0.1:{port}")
+        );
+        assert!(matches!(
+            attempt(&format!("mongodb://127.0.0.1:{port}")),
+            ProbeOutcome::Ready
+        ));
+        match attempt(&format!("mongodb://127.0.0.1:{port}?primary")) {
+            ProbeOutcome::NotYet(error) => {
+                assert_eq!(error.to_string(), "Not a writable primary, db-1:27017 is")
+            }
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn test_mongodb_probe_create() {
+        let probe = MongoProbe::create("mongodb://127.0.0.1").unwrap();
+        assert_eq!(probe.addresses()[0].port(), 27017);
+        for service in ["mongodb://", "mongodb://127.0.0.1?secondary"] {
+            assert_eq!(
+                MongoProbe::create(service).err().unwrap().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use once_cell::sync::Lazy;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::amqp::AmqpProbe;
+use crate::elasticsearch::ElasticsearchProbe;
+use crate::filesystem::FileProbe;
+use crate::grpc::GrpcHealthProbe;
+use crate::kafka::KafkaProbe;
+use crate::mongodb::MongoProbe;
+use crate::network::TcpProbe;
+use crate::process::ProcessProbe;
+use crate::script::ScriptProbe;
+#[cfg(feature = "tls")]
+use crate::tls::TlsProbe;
+
+/// Result of a single attempt, or of waiting as a whole
+#[derive(Debug)]
+pub enum ProbeOutcome {
+    Ready,
+    /// Ready, but with something to point out, e.g. a certificate about to expire
+    ReadyWithWarning(String),
+    /// Worth another attempt later, e.g. a refused connection
+    NotYet(io::Error),
+    /// Not worth any further attempts, e.g. a malformed response
+    Fatal(io::Error),
+}
+
+/// A single kind of check that a service is available
+pub trait Probe: Send + Sync {
+    /// What an attempt does, completing "Attempt 1 to …",
+    /// e.g. "connect to 127.0.0.1:631"
+    fn activity(&self) -> String;
+
+    /// Makes a single attempt, `None` for no timeout
+    fn attempt(&self, timeout: Option<Duration>) -> ProbeOutcome;
+
+    /// Network addresses that a host name was resolved to, the first one being used
+    fn addresses(&self) -> &[SocketAddr] {
+        &[]
+    }
+
+    /// Whether running out of time is reported with the error of the last attempt
+    /// rather than a plain "Time is up", e.g. for the output of a failing command
+    fn reports_last_error(&self) -> bool {
+        false
+    }
+}
+
+/// Creates a probe for a service spec like "tcp://localhost:631";
+/// errors other than [`io::ErrorKind::InvalidInput`] are retried,
+/// e.g. to wait for a host name to become resolvable
+pub type ProbeFactory = fn(&str) -> Result<Box<dyn Probe>, io::Error>;
+
+struct ProbeScheme {
+    probe_factory: ProbeFactory,
+    /// Whether specs need "://" after the scheme, e.g. "kafka://localhost:9092",
+    /// so that "kafka:9092" remains plain "host:port"
+    url_only: bool,
+}
+
+impl ProbeScheme {
+    fn url(probe_factory: ProbeFactory) -> Self {
+        ProbeScheme {
+            probe_factory,
+            url_only: true,
+        }
+    }
+
+    fn opaque(probe_factory: ProbeFactory) -> Self {
+        ProbeScheme {
+            probe_factory,
+            url_only: false,
+        }
+    }
+}
+
+static PROBE_SCHEMES: Lazy<RwLock<HashMap<String, ProbeScheme>>> = Lazy::new(|| {
+    let mut probe_schemes: HashMap<String, ProbeScheme> = HashMap::new();
+    let mut insert = |scheme: &str, probe_scheme| {
+        probe_schemes.insert(String::from(scheme), probe_scheme);
+    };
+    insert("tcp", ProbeScheme::url(TcpProbe::create));
+    insert("file", ProbeScheme::opaque(FileProbe::create_file));
+    insert("dir", ProbeScheme::opaque(FileProbe::create_directory));
+    insert(
+        "pid",
+        ProbeScheme::opaque(ProcessProbe::create_for_pid_file),
+    );
+    insert(
+        "process",
+        ProbeScheme::opaque(ProcessProbe::create_for_name),
+    );
+    insert("grpc", ProbeScheme::url(GrpcHealthProbe::create));
+    insert("amqp", ProbeScheme::url(AmqpProbe::create));
+    insert("kafka", ProbeScheme::url(KafkaProbe::create));
+    insert("mongodb", ProbeScheme::url(MongoProbe::create));
+    insert(
+        "memcached",
+        ProbeScheme::url(ScriptProbe::create_for_memcached),
+    );
+    insert(
+        "zookeeper",
+        ProbeScheme::url(ScriptProbe::create_for_zookeeper),
+    );
+    insert(
+        "elasticsearch",
+        ProbeScheme::url(ElasticsearchProbe::create),
+    );
+    insert("opensearch", ProbeScheme::url(ElasticsearchProbe::create));
+    insert("script", ProbeScheme::url(ScriptProbe::create_for_tcp));
+    insert(
+        "script+unix",
+        ProbeScheme::opaque(ScriptProbe::create_for_unix),
+    );
+    #[cfg(feature = "tls")]
+    insert("tls", ProbeScheme::url(TlsProbe::create));
+    RwLock::new(probe_schemes)
+});
+
+/// Makes services like "cmd:pg_isready" with the given scheme use probes
+/// from the given factory, replacing any previous registration;
+/// "cmd:5432" with nothing but digits after the colon remains "host:port"
+pub fn register_probe_scheme(scheme: &str, probe_factory: ProbeFactory) {
+    let mut probe_schemes = PROBE_SCHEMES.write().expect("poisoned lock");
+    probe_schemes.insert(
+        scheme.to_ascii_lowercase(),
+        ProbeScheme::opaque(probe_factory),
+    );
+}
+
+/// Makes services with the given URL scheme, e.g. "redis" for "redis://localhost:6379",
+/// use probes from the given factory, replacing any previous registration;
+/// "redis:6379" or anything else without "//" after the colon remains "host:port"
+pub fn register_url_probe_scheme(scheme: &str, probe_factory: ProbeFactory) {
+    let mut probe_schemes = PROBE_SCHEMES.write().expect("poisoned lock");
+    probe_schemes.insert(scheme.to_ascii_lowercase(), ProbeScheme::url(probe_factory));
+}
+
+/// URL scheme of a service spec if registered, e.g. "tcp" for "tcp://localhost:631"
+/// but nothing for "localhost:631" or "tcp:631"
+pub fn registered_scheme_of(service: &str) -> Option<String> {
+    let (scheme, rest) = service.split_once(':')?;
+    let mut chars = scheme.chars();
+    if !chars.next()?.is_ascii_alphabetic()
+        || !chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    {
+        return None;
+    }
+    let scheme = scheme.to_ascii_lowercase();
+    let probe_schemes = PROBE_SCHEMES.read().expect("poisoned lock");
+    let is_host_and_port = if probe_schemes.get(&scheme)?.url_only {
+        !rest.starts_with("//")
+    } else {
+        !rest.is_empty() && rest.bytes().all(|byte| byte.is_ascii_digit())
+    };
+    (!is_host_and_port).then_some(scheme)
+}
+
+/// Creates a probe by URL scheme, falling back to TCP for plain "host:port"
+pub(crate) fn create_probe(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+    let probe_factory = match registered_scheme_of(service) {
+        Some(scheme) => {
+            let probe_schemes = PROBE_SCHEMES.read().expect("poisoned lock");
+            probe_schemes[&scheme].probe_factory
+        }
+        None => TcpProbe::create,
+    };
+    probe_factory(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use super::create_probe;
+    use super::register_probe_scheme;
+    use super::register_url_probe_scheme;
+    use super::registered_scheme_of;
+    use super::Probe;
+    use super::ProbeOutcome;
+
+    struct AlwaysReady;
+
+    impl Probe for AlwaysReady {
+        fn activity(&self) -> String {
+            String::from("do nothing")
+        }
+
+        fn attempt(&self, _timeout: Option<Duration>) -> ProbeOutcome {
+            ProbeOutcome::Ready
+        }
+    }
+
+    fn create_always_ready(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        match service.strip_prefix("test-ready:") {
+            Some("") => Err(io::Error::new(io::ErrorKind::InvalidInput, "Nothing")),
+            _ => Ok(Box::new(AlwaysReady)),
+        }
+    }
+
+    #[test]
+    fn test_registered_scheme_of() {
+        assert_eq!(
+            registered_scheme_of("tcp://localhost:631"),
+            Some(String::from("tcp"))
+        );
+        assert_eq!(
+            registered_scheme_of("TCP://localhost:631"),
+            Some(String::from("tcp"))
+        );
+        assert_eq!(registered_scheme_of("localhost:631"), None);
+        assert_eq!(registered_scheme_of("[::1]:631"), None);
+        assert_eq!(registered_scheme_of("no-such-scheme://x"), None);
+    }
+
+    #[test]
+    fn test_registered_scheme_of_for_hosts_named_like_schemes() {
+        for service in [
+            "tcp:631",
+            "file:80",
+            "dir:80",
+            "pid:80",
+            "process:80",
+            "grpc:50051",
+            "amqp:5672",
+            "kafka:9092",
+            "mongodb:27017",
+            "memcached:11211",
+            "zookeeper:2181",
+            "elasticsearch:9200",
+            "opensearch:9200",
+            "script:6379",
+            "script+unix:80",
+            "tls:443",
+            "TCP:631",
+        ] {
+            assert_eq!(registered_scheme_of(service), None, "{service}");
+        }
+    }
+
+    #[test]
+    fn test_register_url_probe_scheme() {
+        register_url_probe_scheme("test-url", create_always_ready);
+        assert_eq!(
+            registered_scheme_of("test-url://x"),
+            Some(String::from("test-url"))
+        );
+        assert_eq!(registered_scheme_of("test-url:x"), None);
+        assert_eq!(registered_scheme_of("test-url:1"), None);
+    }
+
+    #[test]
+    fn test_register_probe_scheme() {
+        assert!(registered_scheme_of("test-ready:x").is_none());
+        register_probe_scheme("Test-Ready", create_always_ready);
+        assert_eq!(
+            registered_scheme_of("test-ready:x"),
+            Some(String::from("test-ready"))
+        );
+
+        let probe = create_probe("test-ready:x").unwrap();
+        assert_eq!(probe.activity(), "do nothing");
+        assert!(matches!(probe.attempt(None), ProbeOutcome::Ready));
+        assert!(probe.addresses().is_empty());
+        assert_eq!(
+            create_probe("test-ready:").err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_create_probe_for_tcp() {
+        let probe = create_probe("tcp://127.0.0.1:631").unwrap();
+        assert_eq!(probe.activity(), "connect to 127.0.0.1:631");
+        let probe = create_probe("127.0.0.1:631").unwrap();
+        assert_eq!(probe.addresses(), ["127.0.0.1:631".parse().unwrap()]);
+        assert!(create_probe("tcp://").is_err());
+    }
+}
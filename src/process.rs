@@ -0,0 +1,272 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::probe::{Probe, ProbeOutcome};
+use crate::util::invalid_input;
+
+enum Target {
+    PidFile(PathBuf),
+    Name(String),
+}
+
+/// Checks that a process is running, for services like "pid:/run/app.pid"
+/// or "process:nginx", or with option "?exited" that it is no longer running
+pub(crate) struct ProcessProbe {
+    target: Target,
+    exited: bool,
+}
+
+/// Separates option "exited" from e.g. "nginx?exited"
+fn parse_spec(service: &str) -> Result<(&str, bool), io::Error> {
+    let spec = service.split_once(':').map_or("", |(_, spec)| spec);
+    let (target, exited) = match spec.split_once('?') {
+        Some((target, "exited")) => (target, true),
+        Some((_, option)) => return Err(invalid_input(format!("Unknown option \"{option}\""))),
+        None => (spec, false),
+    };
+    if target.is_empty() {
+        return Err(invalid_input(String::from("Nothing to look for given")));
+    }
+    Ok((target, exited))
+}
+
+/// Whether a process with the given ID is running, not counting zombies
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_running(pid: u32) -> bool {
+    match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        // NOTE: The state follows the command name in parentheses, e.g. "123 (nginx) S 1 ..."
+        Ok(stat) => stat
+            .rsplit_once(')')
+            .and_then(|(_, rest)| rest.trim_start().chars().next())
+            .is_some_and(|state| state != 'Z' && state != 'X'),
+        Err(_) => false,
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // NOTE: Signal 0 only checks for existence and permission
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// IDs of running processes with the given name, compared against
+/// both the kernel's command name and the file name of the executable
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pids_of(name: &str) -> Result<Vec<u32>, io::Error> {
+    let mut pids = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let Some(pid) = entry?.file_name().to_str().and_then(|pid| pid.parse().ok()) else {
+            continue;
+        };
+        let comm = fs::read_to_string(format!("/proc/{pid}/comm")).unwrap_or_default();
+        let cmdline = fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+        let argv0 = cmdline.split(|byte| *byte == 0).next().unwrap_or_default();
+        let argv0 = String::from_utf8_lossy(argv0);
+        let executable = argv0.rsplit('/').next().unwrap_or_default();
+        if (comm.trim_end() == name || executable == name) && is_running(pid) {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+impl ProcessProbe {
+    pub(crate) fn create_for_pid_file(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let (path, exited) = parse_spec(service)?;
+        Ok(Box::new(ProcessProbe {
+            target: Target::PidFile(PathBuf::from(path)),
+            exited,
+        }))
+    }
+
+    pub(crate) fn create_for_name(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let (name, exited) = parse_spec(service)?;
+        if !cfg!(any(target_os = "linux", target_os = "android")) {
+            return Err(invalid_input(String::from(
+                "Looking for processes by name needs /proc",
+            )));
+        }
+        Ok(Box::new(ProcessProbe {
+            target: Target::Name(name.to_string()),
+            exited,
+        }))
+    }
+
+    /// IDs of matching running processes, empty if there are none
+    fn running_pids(&self) -> Result<Vec<u32>, io::Error> {
+        match &self.target {
+            Target::PidFile(path) => {
+                let content = match fs::read_to_string(path) {
+                    Ok(content) => content,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+                    Err(error) => return Err(error),
+                };
+                let pid: u32 = content.trim().parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} contains no process ID", path.display()),
+                    )
+                })?;
+                Ok([pid].into_iter().filter(|pid| is_running(*pid)).collect())
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Target::Name(name) => pids_of(name),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            Target::Name(_) => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Probe for ProcessProbe {
+    fn activity(&self) -> String {
+        let process = match &self.target {
+            Target::PidFile(path) => format!("process of {}", path.display()),
+            Target::Name(name) => format!("process {name}"),
+        };
+        if self.exited {
+            format!("see {process} exit")
+        } else {
+            format!("find {process}")
+        }
+    }
+
+    fn attempt(&self, _timeout: Option<Duration>) -> ProbeOutcome {
+        let pids = match self.running_pids() {
+            Ok(pids) => pids,
+            // NOTE: A PID file may be read while it is being written
+            Err(error) => return ProbeOutcome::NotYet(error),
+        };
+        match (pids.is_empty(), self.exited) {
+            (false, false) | (true, true) => ProbeOutcome::Ready,
+            (true, false) => ProbeOutcome::NotYet(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No such process running",
+            )),
+            (false, true) => {
+                let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+                ProbeOutcome::NotYet(io::Error::other(format!(
+                    "Still running with PID {}",
+                    pids.join(", ")
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use crate::probe::{Probe, ProbeOutcome};
+
+    use super::is_running;
+    use super::ProcessProbe;
+
+    fn is_ready(probe: &dyn Probe) -> bool {
+        matches!(probe.attempt(None), ProbeOutcome::Ready)
+    }
+
+    /// Covers the `kill`-based variant on macOS and the BSDs, too
+    #[test]
+    fn test_is_running() {
+        assert!(is_running(std::process::id()));
+        assert!(!is_running(u32::MAX));
+    }
+
+    #[test]
+    fn test_process_probe_create_for_invalid() {
+        for service in ["pid:", "pid:?exited", "pid:/run/x.pid?gone"] {
+            assert_eq!(
+                ProcessProbe::create_for_pid_file(service)
+                    .err()
+                    .unwrap()
+                    .kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_probe_for_pid_file() {
+        let path = std::env::temp_dir().join(format!(
+            "rust-for-it-test-process-{}.pid",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let service = format!("pid:{}", path.display());
+        let probe = ProcessProbe::create_for_pid_file(&service).unwrap();
+        let exited_probe = ProcessProbe::create_for_pid_file(&format!("{service}?exited")).unwrap();
+        assert_eq!(
+            probe.activity(),
+            format!("find process of {}", path.display())
+        );
+        assert!(!is_ready(probe.as_ref()));
+        assert!(is_ready(exited_probe.as_ref()));
+
+        fs::write(&path, "not a number").unwrap();
+        assert!(!is_ready(probe.as_ref()));
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        fs::write(&path, format!("{}\n", child.id())).unwrap();
+        assert!(is_ready(probe.as_ref()));
+        assert!(!is_ready(exited_probe.as_ref()));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(!is_ready(probe.as_ref()));
+        assert!(is_ready(exited_probe.as_ref()));
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn test_process_probe_for_name() {
+        let name = format!("rfi-test-{}", std::process::id());
+        let script = std::env::temp_dir().join(&name);
+        fs::write(&script, "#!/bin/sh\nsleep 10\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let probe = ProcessProbe::create_for_name(&format!("process:{name}")).unwrap();
+        assert_eq!(probe.activity(), format!("find process {name}"));
+        assert!(!is_ready(probe.as_ref()));
+
+        // NOTE: The kernel names the process of a script after the script
+        let mut child = Command::new(&script).spawn().unwrap();
+        let exited_probe =
+            ProcessProbe::create_for_name(&format!("process:{name}?exited")).unwrap();
+        let started = (0..50).any(|_| {
+            sleep(Duration::from_millis(20));
+            is_ready(probe.as_ref())
+        });
+        assert!(started);
+        assert!(!is_ready(exited_probe.as_ref()));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(is_ready(exited_probe.as_ref()));
+
+        let _ = fs::remove_file(&script);
+    }
+}
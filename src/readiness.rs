@@ -0,0 +1,314 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use log::{error, info};
+use rust_for_it::{display_form_of, probe_service_since};
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime};
+
+use crate::logging::{with_logging_for_current_thread, SubLevel};
+
+/// Pause in between two probes of the same service
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time that clients get to send their request headers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Upper bound for connections being answered in parallel
+const MAX_CONNECTION_THREADS: usize = 8;
+
+/// Latest probe result per service, `None` until probed for the first time
+pub(crate) struct Readiness {
+    services: Mutex<Vec<(String, Option<bool>)>>,
+}
+
+impl Readiness {
+    pub(crate) fn new<S: AsRef<str>>(services: &[S]) -> Self {
+        let mut states: Vec<(String, Option<bool>)> = Vec::new();
+        for service in services {
+            let service = display_form_of(service.as_ref());
+            if !states.iter().any(|(known, _)| *known == service) {
+                states.push((service, None));
+            }
+        }
+        Readiness {
+            services: Mutex::new(states),
+        }
+    }
+
+    /// Records a probe result, returns `true` if it differs from the previous one
+    fn set_available(&self, service: &str, available: bool) -> bool {
+        let mut services = self.services.lock().expect("poisoned lock");
+        match services.iter_mut().find(|(known, _)| known == service) {
+            Some((_, state)) => state.replace(available) != Some(available),
+            None => false,
+        }
+    }
+
+    fn unavailable_services(&self) -> Vec<String> {
+        let services = self.services.lock().expect("poisoned lock");
+        services
+            .iter()
+            .filter(|(_, state)| *state != Some(true))
+            .map(|(service, _)| service.clone())
+            .collect()
+    }
+}
+
+/// Probes a service forever, each probe limited by the given timeout,
+/// with option "newer" referring to when re-probing started
+fn probe_continuously(
+    readiness: &Readiness,
+    service_spec: &str,
+    timeout: Option<Duration>,
+    started: SystemTime,
+) -> ! {
+    let service = display_form_of(service_spec);
+    let service = service.as_str();
+    loop {
+        let probe_result = probe_service_since(service_spec, timeout, started);
+        if readiness.set_available(service, probe_result.is_ok()) {
+            match probe_result {
+                Ok(_) => {
+                    info!(target: module_path!(), sublevel = SubLevel::Succeeded,
+                        event = "available", service = service, result = "available";
+                        "{service} is available.");
+                }
+                Err(error) => {
+                    let error_kind = format!("{:?}", error.kind());
+                    error!(target: module_path!(), event = "unavailable", service = service,
+                        error_kind = error_kind.as_str(), error:% = error, result = "unavailable";
+                        "{service} is unavailable ({error}).");
+                }
+            }
+        }
+        sleep(PROBE_INTERVAL);
+    }
+}
+
+fn reason_phrase_of(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    }
+}
+
+/// Picks status code and body for the given request head
+fn respond_to(request: &str, readiness: &Readiness) -> (u16, String) {
+    let mut request_line_parts = request.lines().next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line_parts.next(), request_line_parts.next())
+    else {
+        return (400, String::from("bad request\n"));
+    };
+    let path = target.split('?').next().unwrap_or_default();
+
+    if method != "GET" && method != "HEAD" {
+        return (405, String::from("method not allowed\n"));
+    }
+    if path != "/healthz" && path != "/readyz" {
+        return (404, String::from("not found\n"));
+    }
+
+    let unavailable_services = readiness.unavailable_services();
+    if unavailable_services.is_empty() {
+        (200, String::from("ok\n"))
+    } else {
+        (
+            503,
+            format!("unavailable: {}\n", unavailable_services.join(", ")),
+        )
+    }
+}
+
+fn render_response(status: u16, body: &str, include_body: bool) -> String {
+    format!(
+        "HTTP/1.1 {status} {}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        reason_phrase_of(status),
+        body.len(),
+        if include_body { body } else { "" }
+    )
+}
+
+fn handle_connection(mut stream: TcpStream, readiness: &Readiness) {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(length) => request.extend_from_slice(&buffer[..length]),
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            break;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let (status, body) = respond_to(&request, readiness);
+    let include_body = !request.starts_with("HEAD ");
+    let _ = stream.write_all(render_response(status, &body, include_body).as_bytes());
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn serve(listener: TcpListener, readiness: Arc<Readiness>) -> ! {
+    let busy_threads = Arc::new(AtomicUsize::new(0));
+    loop {
+        let Ok((stream, _)) = listener.accept() else {
+            continue;
+        };
+        // NOTE: Past the limit, connections are answered inline so that
+        //       slow clients hold up accepting rather than piling up threads
+        if busy_threads.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTION_THREADS {
+            busy_threads.fetch_sub(1, Ordering::SeqCst);
+            handle_connection(stream, &readiness);
+            continue;
+        }
+        let (readiness, busy_threads) = (readiness.clone(), busy_threads.clone());
+        spawn(move || {
+            handle_connection(stream, &readiness);
+            busy_threads.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Re-probes all services forever while answering `/healthz` and `/readyz`
+/// on the given address, returns only if that address cannot be listened on
+pub(crate) fn run_readiness_sidecar<S: AsRef<str>>(
+    address: SocketAddr,
+    services: &[S],
+    timeout: Option<Duration>,
+) -> i32 {
+    let started = SystemTime::now();
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Could not listen on {address} for readiness requests ({error}).");
+            return 1;
+        }
+    };
+
+    info!(target: module_path!(), sublevel = SubLevel::Starting, event = "serving",
+        address:% = address;
+        "Serving /healthz and /readyz on {address}...");
+
+    let readiness = Arc::new(Readiness::new(services));
+    for service in services {
+        let readiness = readiness.clone();
+        let service = service.as_ref().to_string();
+        spawn(move || {
+            with_logging_for_current_thread(|| {
+                probe_continuously(&readiness, &service, timeout, started)
+            })
+        });
+    }
+
+    serve(listener, readiness)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::spawn;
+
+    use super::handle_connection;
+    use super::render_response;
+    use super::respond_to;
+    use super::Readiness;
+
+    #[test]
+    fn test_readiness() {
+        let readiness = Readiness::new(&["a:1", "b:2", "a:1"]);
+        assert_eq!(readiness.unavailable_services(), vec!["a:1", "b:2"]);
+
+        assert!(readiness.set_available("a:1", true));
+        assert!(!readiness.set_available("a:1", true));
+        assert!(!readiness.set_available("c:3", true));
+        assert_eq!(readiness.unavailable_services(), vec!["b:2"]);
+
+        assert!(readiness.set_available("b:2", false));
+        assert!(readiness.set_available("b:2", true));
+        assert!(readiness.unavailable_services().is_empty());
+    }
+
+    #[test]
+    fn test_respond_to() {
+        let readiness = Readiness::new(&["a:1", "b:2"]);
+        readiness.set_available("a:1", true);
+
+        assert_eq!(
+            respond_to("GET /readyz HTTP/1.1\r\n\r\n", &readiness),
+            (503, String::from("unavailable: b:2\n"))
+        );
+        readiness.set_available("b:2", true);
+        assert_eq!(
+            respond_to("GET /readyz HTTP/1.1\r\n\r\n", &readiness),
+            (200, String::from("ok\n"))
+        );
+        assert_eq!(
+            respond_to("HEAD /healthz?verbose HTTP/1.1\r\n\r\n", &readiness),
+            (200, String::from("ok\n"))
+        );
+        assert_eq!(respond_to("GET /other HTTP/1.1\r\n\r\n", &readiness).0, 404);
+        assert_eq!(
+            respond_to("POST /readyz HTTP/1.1\r\n\r\n", &readiness).0,
+            405
+        );
+        assert_eq!(respond_to("", &readiness).0, 400);
+    }
+
+    #[test]
+    fn test_render_response() {
+        assert_eq!(
+            render_response(200, "ok\n", true),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 3\r\n\
+             Connection: close\r\n\
+             \r\n\
+             ok\n"
+        );
+        assert!(render_response(503, "unavailable: a:1\n", false)
+            .ends_with("Content-Length: 17\r\nConnection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = spawn(move || {
+            let readiness = Readiness::new(&["a:1"]);
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &readiness);
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.ends_with("\r\n\r\nunavailable: a:1\n"));
+    }
+}
@@ -0,0 +1,598 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use regex::bytes::Regex as BytesRegex;
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::network::{connect, resolve_host_and_port, resolve_host_with_default_port};
+use crate::probe::{Probe, ProbeOutcome};
+use crate::util::invalid_input;
+
+/// Upper bound for bytes received but not yet matched by any "expect=" step
+const MAX_RECEIVED_BYTES: usize = 65_536;
+
+enum Target {
+    Tcp(Vec<SocketAddr>),
+    Unix(PathBuf),
+}
+
+enum Step {
+    Send(Vec<u8>),
+    Expect(BytesRegex),
+    /// Applies to all following steps
+    Timeout(Duration),
+}
+
+/// Runs a send/expect dialogue, for services like
+/// "script://localhost:6379?send=PING\r\n&expect=^\+PONG" via TCP
+/// or "script+unix:/run/app.sock?send=status\n&timeout=0.5&expect=ok" via a Unix socket
+///
+/// Steps go after a question mark and are separated by ampersands:
+/// "send=<bytes>" with escapes \r, \n, \t, \0, \xHH, \\ and \&,
+/// "expect=<regex>" for the bytes received since the last match,
+/// and "timeout=<seconds>" for each of the steps that follow.
+///
+/// Services "memcached://host[:port]" and "zookeeper://host[:port]" use predefined
+/// dialogues, see [`ScriptProbe::create_for_memcached`] and
+/// [`ScriptProbe::create_for_zookeeper`].
+pub(crate) struct ScriptProbe {
+    target: Target,
+    steps: Vec<Step>,
+    /// Start of the activity, e.g. "run a script against" for "run a script against [..]"
+    activity_prefix: &'static str,
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buffer),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buffer),
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.write_all(bytes),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write_all(bytes),
+        }
+    }
+
+    fn shutdown(&self) {
+        let _ = match self {
+            Connection::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+/// Splits at ampersands that are not escaped by a backslash, keeping escapes as they are
+fn split_steps(steps: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in steps.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '&' => {
+                parts.push(&steps[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&steps[start..]);
+    parts
+}
+
+/// Decodes \r, \n, \t, \0, \xHH, \\ and \& for "send="
+fn unescape(text: &str) -> Result<Vec<u8>, io::Error> {
+    let mut bytes = Vec::new();
+    let mut rest = text;
+    while let Some(index) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..index]);
+        let escape = &rest[index + 1..];
+        let (byte, length) = match escape.chars().next() {
+            Some('r') => (b'\r', 1),
+            Some('n') => (b'\n', 1),
+            Some('t') => (b'\t', 1),
+            Some('0') => (0, 1),
+            Some('\\') => (b'\\', 1),
+            Some('&') => (b'&', 1),
+            Some('x') => match escape.get(1..3).map(|hex| u8::from_str_radix(hex, 16)) {
+                Some(Ok(byte)) => (byte, 3),
+                _ => {
+                    return Err(invalid_input(String::from(
+                        "Escape \\x needs two hex digits",
+                    )))
+                }
+            },
+            _ => return Err(invalid_input(format!("Unknown escape in \"{text}\""))),
+        };
+        bytes.push(byte);
+        rest = &escape[length..];
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    Ok(bytes)
+}
+
+fn parse_step(step: &str) -> Result<Step, io::Error> {
+    match step.split_once('=') {
+        Some(("send", bytes)) => Ok(Step::Send(unescape(bytes)?)),
+        // NOTE: "\&" is a valid escape in regular expressions, too
+        Some(("expect", pattern)) => BytesRegex::new(pattern)
+            .map(Step::Expect)
+            .map_err(|error| invalid_input(format!("Invalid regular expression: {error}"))),
+        Some(("timeout", seconds)) => seconds
+            .parse()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .filter(|timeout| !timeout.is_zero())
+            .map(Step::Timeout)
+            .ok_or_else(|| invalid_input(format!("Invalid timeout \"{seconds}\""))),
+        _ => Err(invalid_input(format!("Unknown step \"{step}\""))),
+    }
+}
+
+/// Received bytes for error messages, e.g. "+OK\r\n"
+fn escape_received(received: &[u8]) -> String {
+    String::from_utf8_lossy(received).escape_debug().to_string()
+}
+
+/// Parses steps up front, so that typos are fatal rather than retried
+fn parse_steps(steps: &str) -> Result<Vec<Step>, io::Error> {
+    let steps = split_steps(steps)
+        .into_iter()
+        .filter(|step| !step.is_empty())
+        .map(parse_step)
+        .collect::<Result<Vec<Step>, io::Error>>()?;
+    if steps.is_empty() {
+        return Err(invalid_input(String::from("No steps given")));
+    }
+    Ok(steps)
+}
+
+impl ScriptProbe {
+    /// Resolves "script://host:port?<steps>" once
+    pub(crate) fn create_for_tcp(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let spec = service.split_once("://").map_or("", |(_, spec)| spec);
+        let (host_and_port, steps) = spec.split_once('?').unwrap_or((spec, ""));
+        if host_and_port.is_empty() {
+            return Err(invalid_input(String::from("No host and port given")));
+        }
+        let steps = parse_steps(steps)?;
+        let addresses = resolve_host_and_port(host_and_port)?;
+        Ok(Box::new(ScriptProbe {
+            target: Target::Tcp(addresses),
+            steps,
+            activity_prefix: "run a script against",
+        }))
+    }
+
+    pub(crate) fn create_for_unix(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let spec = service.split_once(':').map_or("", |(_, spec)| spec);
+        let (path, steps) = spec.split_once('?').unwrap_or((spec, ""));
+        if path.is_empty() {
+            return Err(invalid_input(String::from("No socket path given")));
+        }
+        if !cfg!(unix) {
+            return Err(invalid_input(String::from(
+                "Unix sockets are not supported here",
+            )));
+        }
+        Ok(Box::new(ScriptProbe {
+            target: Target::Unix(PathBuf::from(path)),
+            steps: parse_steps(steps)?,
+            activity_prefix: "run a script against",
+        }))
+    }
+
+    /// Splits "<scheme>://host[:port][?<option>]" and resolves the host once
+    fn resolve_preset_spec(
+        service: &str,
+        default_port: u16,
+    ) -> Result<(Vec<SocketAddr>, &str), io::Error> {
+        let spec = service.split_once("://").map_or("", |(_, spec)| spec);
+        let (host_and_port, option) = spec.split_once('?').unwrap_or((spec, ""));
+        if host_and_port.is_empty() {
+            return Err(invalid_input(String::from("No host given")));
+        }
+        let addresses = resolve_host_with_default_port(host_and_port, default_port)?;
+        Ok((addresses, option))
+    }
+
+    /// Sends "version" and expects "VERSION", for services like "memcached://localhost:11211"
+    pub(crate) fn create_for_memcached(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let (addresses, option) = ScriptProbe::resolve_preset_spec(service, 11211)?;
+        if !option.is_empty() {
+            return Err(invalid_input(format!("Unknown option \"{option}\"")));
+        }
+        Ok(Box::new(ScriptProbe {
+            target: Target::Tcp(addresses),
+            steps: vec![
+                Step::Send(b"version\r\n".to_vec()),
+                Step::Expect(BytesRegex::new(r"^VERSION ").unwrap()),
+            ],
+            activity_prefix: "query memcached at",
+        }))
+    }
+
+    /// Sends four-letter word "srvr" and expects a mode line, for services like
+    /// "zookeeper://localhost:2181", with option "?mode=<mode>" for a particular mode
+    /// like "leader" or "follower", or with option "?ruok" sends "ruok" and expects "imok"
+    ///
+    /// NOTE: Only "srvr" is allowed by default as of ZooKeeper 3.5.3,
+    ///       others need to be allowed by setting 4lw.commands.whitelist.
+    pub(crate) fn create_for_zookeeper(service: &str) -> Result<Box<dyn Probe>, io::Error> {
+        let (addresses, option) = ScriptProbe::resolve_preset_spec(service, 2181)?;
+        let (word, pattern) = match option.split_once('=') {
+            None if option.is_empty() => ("srvr", String::from(r"(?m)^Mode: ")),
+            None if option == "ruok" => ("ruok", String::from(r"^imok")),
+            Some(("mode", mode)) if !mode.is_empty() => {
+                ("srvr", format!(r"(?m)^Mode: {}\r?$", regex::escape(mode)))
+            }
+            _ => return Err(invalid_input(format!("Unknown option \"{option}\""))),
+        };
+        Ok(Box::new(ScriptProbe {
+            target: Target::Tcp(addresses),
+            steps: vec![
+                Step::Send(word.as_bytes().to_vec()),
+                Step::Expect(BytesRegex::new(&pattern).unwrap()),
+            ],
+            activity_prefix: "query ZooKeeper at",
+        }))
+    }
+
+    fn open(&self, timeout: Option<Duration>) -> io::Result<Connection> {
+        match &self.target {
+            Target::Tcp(addresses) => connect(&addresses[0], timeout).map(Connection::Tcp),
+            #[cfg(unix)]
+            Target::Unix(path) => UnixStream::connect(path).map(Connection::Unix),
+            #[cfg(not(unix))]
+            Target::Unix(_) => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+
+    fn run(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut connection = self.open(timeout)?;
+        let mut step_timeout: Option<Duration> = None;
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 4096];
+        for step in &self.steps {
+            let step_deadline = step_timeout.map(|timeout| Instant::now() + timeout);
+            let step_deadline = match (deadline, step_deadline) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            let time_left = || {
+                step_deadline
+                    .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+                    .map(|time_left| time_left.max(Duration::from_millis(1)))
+            };
+
+            match step {
+                Step::Send(bytes) => {
+                    connection.set_timeout(time_left())?;
+                    connection.write_all(bytes)?;
+                }
+                Step::Expect(matcher) => loop {
+                    if let Some(found) = matcher.find(&received) {
+                        received.drain(..found.end());
+                        break;
+                    }
+                    let timed_out = |received: &[u8]| {
+                        io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!(
+                                "Timed out expecting \"{matcher}\", received \"{}\"",
+                                escape_received(received)
+                            ),
+                        )
+                    };
+                    if step_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(timed_out(&received));
+                    }
+                    connection.set_timeout(time_left())?;
+                    let count = match connection.read(&mut buffer) {
+                        Ok(count) => count,
+                        Err(error)
+                            if matches!(
+                                error.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            return Err(timed_out(&received));
+                        }
+                        Err(error) => return Err(error),
+                    };
+                    if count == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            format!(
+                                "Connection closed while expecting \"{matcher}\", received \"{}\"",
+                                escape_received(&received)
+                            ),
+                        ));
+                    }
+                    received.extend_from_slice(&buffer[..count]);
+                    if received.len() > MAX_RECEIVED_BYTES {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Received more than {MAX_RECEIVED_BYTES} bytes without a match for \"{matcher}\""),
+                        ));
+                    }
+                },
+                Step::Timeout(timeout) => step_timeout = Some(*timeout),
+            }
+        }
+        connection.shutdown();
+        Ok(())
+    }
+}
+
+impl Probe for ScriptProbe {
+    fn activity(&self) -> String {
+        match &self.target {
+            Target::Tcp(addresses) => format!("{} {}", self.activity_prefix, addresses[0]),
+            Target::Unix(path) => format!("{} {}", self.activity_prefix, path.display()),
+        }
+    }
+
+    fn attempt(&self, timeout: Option<Duration>) -> ProbeOutcome {
+        match self.run(timeout) {
+            Ok(()) => ProbeOutcome::Ready,
+            Err(error) => ProbeOutcome::NotYet(error),
+        }
+    }
+
+    fn addresses(&self) -> &[SocketAddr] {
+        match &self.target {
+            Target::Tcp(addresses) => addresses,
+            Target::Unix(_) => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    use crate::probe::{Probe, ProbeOutcome};
+
+    use super::split_steps;
+    use super::unescape;
+    use super::ScriptProbe;
+
+    /// Answers "PING" lines with "+PONG" and ignores anything else
+    fn serve_pings<S: io::Read + Write>(stream: S) {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            if line == "PING\r\n" {
+                let _ = reader.get_mut().write_all(b"+PONG\r\n");
+            }
+            line.clear();
+        }
+    }
+
+    fn start_ping_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || {
+            for stream in listener.incoming().flatten() {
+                spawn(move || serve_pings(stream));
+            }
+        });
+        port
+    }
+
+    fn attempt(probe: &dyn Probe) -> ProbeOutcome {
+        probe.attempt(Some(Duration::from_secs(5)))
+    }
+
+    #[test]
+    fn test_split_steps_and_unescape() {
+        assert_eq!(
+            split_steps(r"send=a\&b&expect=c"),
+            [r"send=a\&b", "expect=c"]
+        );
+        assert_eq!(split_steps(r"send=a\\&b"), [r"send=a\\", "b"]);
+        assert_eq!(
+            unescape(r"\x00\xfF\r\n\t\0\\\&é").unwrap(),
+            b"\x00\xff\r\n\t\0\\&\xc3\xa9"
+        );
+        assert!(unescape(r"\x1").is_err());
+        assert!(unescape(r"\q").is_err());
+    }
+
+    #[test]
+    fn test_script_probe_create_for_invalid() {
+        for service in [
+            "script://127.0.0.1:1",
+            "script://?send=x",
+            "script://127.0.0.1:1?expect=(",
+            "script://127.0.0.1:1?timeout=0&send=x",
+            "script://127.0.0.1:1?recv=x",
+        ] {
+            let error = ScriptProbe::create_for_tcp(service).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{service}");
+        }
+        for service in ["script+unix:?send=x", "script+unix:/run/x.sock"] {
+            let error = ScriptProbe::create_for_unix(service).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{service}");
+        }
+    }
+
+    #[test]
+    fn test_script_probe_for_tcp() {
+        let port = start_ping_server();
+        let probe = ScriptProbe::create_for_tcp(&format!(
+            "script://127.0.0.1:{port}?send=PING\\r\\n&expect=^\\+PONG\\r\\n&send=PING\\r\\n&expect=PONG"
+        ))
+        .unwrap();
+        assert_eq!(
+            probe.activity(),
+            format!("run a script against 127.0.0.1:{port}")
+        );
+        assert!(matches!(attempt(probe.as_ref()), ProbeOutcome::Ready));
+
+        let probe = ScriptProbe::create_for_tcp(&format!(
+            "script://127.0.0.1:{port}?send=PING\\r\\n&timeout=0.1&expect=PANG"
+        ))
+        .unwrap();
+        match attempt(probe.as_ref()) {
+            ProbeOutcome::NotYet(error) => assert_eq!(
+                error.to_string(),
+                "Timed out expecting \"PANG\", received \"+PONG\\r\\n\""
+            ),
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_script_probe_for_unix() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!(
+            "rust-for-it-test-script-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let service = format!("script+unix:{}?send=PING\\r\\n&expect=PONG", path.display());
+        let probe = ScriptProbe::create_for_unix(&service).unwrap();
+        assert_eq!(
+            probe.activity(),
+            format!("run a script against {}", path.display())
+        );
+        assert!(matches!(attempt(probe.as_ref()), ProbeOutcome::NotYet(_)));
+
+        let listener = UnixListener::bind(&path).unwrap();
+        spawn(move || {
+            for stream in listener.incoming().flatten() {
+                spawn(move || serve_pings(stream));
+            }
+        });
+        assert!(matches!(attempt(probe.as_ref()), ProbeOutcome::Ready));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// Answers four-letter words like a ZooKeeper follower, then disconnects
+    fn start_zookeeper_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut word = [0u8; 4];
+                if stream.read_exact(&mut word).is_err() {
+                    continue;
+                }
+                let answer: &[u8] = match &word {
+                    b"ruok" => b"imok",
+                    b"srvr" => b"Zookeeper version: 3.9.2\nLatency min/avg/max: 0/0.0/0\nMode: follower\nNode count: 5\n",
+                    _ => b"",
+                };
+                let _ = stream.write_all(answer);
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_script_probe_for_memcached() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                if reader.read_line(&mut line).is_ok() && line == "version\r\n" {
+                    let _ = reader.get_mut().write_all(b"VERSION 1.6.21\r\n");
+                }
+            }
+        });
+        let probe =
+            ScriptProbe::create_for_memcached(&format!("memcached://127.0.0.1:{port}")).unwrap();
+        assert_eq!(
+            probe.activity(),
+            format!("query memcached at 127.0.0.1:{port}")
+        );
+        assert!(matches!(attempt(probe.as_ref()), ProbeOutcome::Ready));
+
+        let probe = ScriptProbe::create_for_memcached("memcached://127.0.0.1").unwrap();
+        assert_eq!(probe.addresses()[0].port(), 11211);
+        assert!(ScriptProbe::create_for_memcached("memcached://127.0.0.1?x").is_err());
+    }
+
+    #[test]
+    fn test_script_probe_for_zookeeper() {
+        let port = start_zookeeper_server();
+        for option in ["", "?ruok", "?mode=follower"] {
+            let service = format!("zookeeper://127.0.0.1:{port}{option}");
+            let probe = ScriptProbe::create_for_zookeeper(&service).unwrap();
+            assert!(
+                matches!(attempt(probe.as_ref()), ProbeOutcome::Ready),
+                "{service}"
+            );
+        }
+
+        let service = format!("zookeeper://127.0.0.1:{port}?mode=leader");
+        let probe = ScriptProbe::create_for_zookeeper(&service).unwrap();
+        match attempt(probe.as_ref()) {
+            ProbeOutcome::NotYet(error) => assert!(error
+                .to_string()
+                .starts_with("Connection closed while expecting \"(?m)^Mode: leader\\r?$\"")),
+            outcome => panic!("Expected NotYet, got {outcome:?}"),
+        }
+
+        for service in [
+            "zookeeper://",
+            "zookeeper://127.0.0.1?mode=",
+            "zookeeper://127.0.0.1?stat",
+        ] {
+            assert_eq!(
+                ScriptProbe::create_for_zookeeper(service)
+                    .err()
+                    .unwrap()
+                    .kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+}
@@ -0,0 +1,300 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use log::{debug, error, info, trace, warn};
+use rust_for_it::WaitEvent;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::logging::SubLevel;
+
+/// Seconds rounded to milliseconds, for machine-readable output
+pub(crate) fn seconds_of(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1000.0).round() / 1000.0
+}
+
+fn addresses_text_of(addresses: &[SocketAddr]) -> String {
+    addresses
+        .iter()
+        .map(|address| address.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Turns progress of the waiter into status messages
+pub(crate) fn log_wait_event(event: &WaitEvent) {
+    match event {
+        WaitEvent::Waiting {
+            service,
+            timeout: None,
+        } => {
+            info!(target: module_path!(), sublevel = SubLevel::Starting, event = "waiting",
+                service = service.as_str(), timeout = 0;
+                "Waiting for {service} without a timeout...");
+        }
+        WaitEvent::Waiting {
+            service,
+            timeout: Some(timeout),
+        } => {
+            let timeout_seconds = timeout.as_secs_f64();
+            info!(target: module_path!(), sublevel = SubLevel::Starting, event = "waiting",
+                service = service.as_str(), timeout = timeout.as_secs();
+                "Waiting {timeout_seconds} seconds for {service}...");
+        }
+        WaitEvent::Resolved {
+            service,
+            address,
+            addresses,
+        } => {
+            let addresses_text = addresses_text_of(addresses);
+            debug!(target: module_path!(), event = "resolved",
+                service = service.as_str(), address:% = address;
+                "Resolved {service} to {addresses_text}, using {address}.");
+        }
+        WaitEvent::ResolutionFailed {
+            service,
+            error_kind,
+            error,
+            backoff: None,
+        } => {
+            let error_kind = format!("{error_kind:?}");
+            debug!(target: module_path!(), event = "resolution_failed",
+                service = service.as_str(), error_kind = error_kind.as_str(),
+                error = error.as_str();
+                "Resolving {service} failed ({error}).");
+        }
+        WaitEvent::ResolutionFailed {
+            service,
+            error_kind,
+            error,
+            backoff: Some(backoff),
+        } => {
+            let error_kind = format!("{error_kind:?}");
+            debug!(target: module_path!(), event = "resolution_failed",
+                service = service.as_str(), error_kind = error_kind.as_str(),
+                error = error.as_str(), backoff = seconds_of(*backoff);
+                "Resolving {service} failed ({error}), retrying in {:.1} seconds.",
+                backoff.as_secs_f32());
+        }
+        WaitEvent::AttemptStarted {
+            service,
+            address,
+            activity,
+            attempt,
+        } => {
+            let address = address.map(|address| address.to_string());
+            trace!(target: module_path!(), event = "attempt_started",
+                service = service.as_str(), address = address.as_deref(), attempt = attempt;
+                "Attempt {attempt} to {activity}...");
+        }
+        WaitEvent::AttemptSucceeded {
+            service,
+            address,
+            activity,
+            attempt,
+        } => {
+            let address = address.map(|address| address.to_string());
+            trace!(target: module_path!(), event = "attempt_succeeded",
+                service = service.as_str(), address = address.as_deref(), attempt = attempt;
+                "Attempt {attempt} to {activity} succeeded.");
+        }
+        WaitEvent::AttemptFailed {
+            service,
+            address,
+            activity,
+            attempt,
+            error_kind,
+            error,
+            backoff: None,
+        } => {
+            let address = address.map(|address| address.to_string());
+            let error_kind = format!("{error_kind:?}");
+            debug!(target: module_path!(), event = "attempt_failed",
+                service = service.as_str(), address = address.as_deref(), attempt = attempt,
+                error_kind = error_kind.as_str(), error = error.as_str();
+                "Attempt {attempt} to {activity} failed ({error}).");
+        }
+        WaitEvent::AttemptFailed {
+            service,
+            address,
+            activity,
+            attempt,
+            error_kind,
+            error,
+            backoff: Some(backoff),
+        } => {
+            let address = address.map(|address| address.to_string());
+            let error_kind = format!("{error_kind:?}");
+            debug!(target: module_path!(), event = "attempt_failed",
+                service = service.as_str(), address = address.as_deref(), attempt = attempt,
+                error_kind = error_kind.as_str(), error = error.as_str(),
+                backoff = seconds_of(*backoff);
+                "Attempt {attempt} to {activity} failed ({error}), retrying in {:.1} seconds.",
+                backoff.as_secs_f32());
+        }
+        WaitEvent::Available {
+            service,
+            address,
+            attempts,
+            elapsed,
+        } => {
+            let address = address.map(|address| address.to_string());
+            let duration = elapsed.as_secs_f32().max(0.1);
+            info!(target: module_path!(), sublevel = SubLevel::Succeeded, event = "available",
+                service = service.as_str(), address = address.as_deref(), attempt = attempts,
+                elapsed = seconds_of(*elapsed), result = "available";
+                "{service} is available after {duration:.1} seconds.");
+        }
+        WaitEvent::TimedOut {
+            service,
+            address,
+            attempts,
+            elapsed,
+            error_kind,
+            error,
+            message,
+            ..
+        } => {
+            let address = address.map(|address| address.to_string());
+            let error_kind = format!("{error_kind:?}");
+            error!(target: module_path!(), event = "timed_out",
+                service = service.as_str(), address = address.as_deref(), attempt = attempts,
+                elapsed = seconds_of(*elapsed), error_kind = error_kind.as_str(),
+                error = error.as_str(), result = "timed_out";
+                "{message}");
+        }
+        WaitEvent::Warning { service, message } => {
+            warn!(target: module_path!(), event = "warning",
+                service = service.as_str();
+                "{message}");
+        }
+        WaitEvent::Failed {
+            service,
+            address,
+            attempts,
+            elapsed,
+            error_kind,
+            error,
+            message,
+        } => {
+            let address = address.map(|address| address.to_string());
+            let error_kind = format!("{error_kind:?}");
+            error!(target: module_path!(), event = "failed",
+                service = service.as_str(), address = address.as_deref(), attempt = attempts,
+                elapsed = seconds_of(*elapsed), error_kind = error_kind.as_str(),
+                error = error.as_str(), result = "failed";
+                "{message}");
+        }
+        WaitEvent::Cancelled {
+            service,
+            attempts,
+            elapsed,
+        } => {
+            debug!(target: module_path!(), event = "cancelled",
+                service = service.as_str(), attempt = attempts,
+                elapsed = seconds_of(*elapsed), result = "cancelled";
+                "Stopped waiting for {service} since another service is available.");
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::formatdoc;
+    use rust_for_it::Waiter;
+
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use crate::main_tests::with_output_captured;
+
+    use super::log_wait_event;
+    use super::seconds_of;
+
+    #[test]
+    fn test_seconds_of() {
+        assert_eq!(seconds_of(Duration::from_micros(1_234_567)), 1.235);
+    }
+
+    #[test]
+    fn test_log_wait_event_for_good() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert_eq!(
+            with_output_captured(|_, _| {
+                Waiter::new()
+                    .service(format!("127.0.0.1:{port}"))
+                    .without_timeout()
+                    .on_event(log_wait_event)
+                    .wait()
+                    .is_success()
+            }),
+            (
+                true,
+                String::from(formatdoc! {"\
+                    [*] Waiting for 127.0.0.1:{port} without a timeout...
+                    [+] 127.0.0.1:{port} is available after 0.1 seconds.
+                "}),
+                String::new()
+            )
+        );
+        assert_eq!(
+            with_output_captured(|_, _| {
+                Waiter::new()
+                    .service(format!("127.0.0.1:{port}"))
+                    .timeout(Duration::from_secs(1))
+                    .on_event(log_wait_event)
+                    .wait()
+                    .is_success()
+            }),
+            (
+                true,
+                String::from(formatdoc! {"\
+                    [*] Waiting 1 seconds for 127.0.0.1:{port}...
+                    [+] 127.0.0.1:{port} is available after 0.1 seconds.
+                "}),
+                String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn test_log_wait_event_for_bad() {
+        let port;
+        {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            port = listener.local_addr().unwrap().port();
+            // NOTE: The listener stops listening when going out of scope
+        }
+
+        let (is_error, stdout, stderr) = with_output_captured(|_, _| {
+            !Waiter::new()
+                .service(format!("127.0.0.1:{port}"))
+                .timeout(Duration::from_secs(1))
+                .on_event(log_wait_event)
+                .wait()
+                .is_success()
+        });
+        assert_eq!(
+            (is_error, stdout),
+            (
+                true,
+                String::from(formatdoc! {"\
+                    [*] Waiting 1 seconds for 127.0.0.1:{port}...
+                "})
+            )
+        );
+        let error_a = String::from(formatdoc! {"\
+            [-] 127.0.0.1:{port} timed out after waiting for 1 seconds (connection timed out).
+        "});
+        let error_b = String::from(formatdoc! {"\
+            [-] 127.0.0.1:{port} timed out after waiting for 1 seconds (Time is up).
+        "});
+        assert!(stderr == error_a || stderr == error_b);
+    }
+}
@@ -0,0 +1,15 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+//! Helpers shared by the tests of several modules
+
+use std::net::TcpListener;
+
+/// Port on 127.0.0.1 that nothing listens on, at least for a moment
+pub(crate) fn closed_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+    // NOTE: The listener stops listening when going out of scope
+}
@@ -0,0 +1,98 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum TimestampFormat {
+    /// Wall clock time in UTC, e.g. "2023-06-01T12:34:56.789Z"
+    Rfc3339,
+    /// Seconds since start, e.g. "12.345", immune to clock changes
+    Monotonic,
+}
+
+/// Converts days since 1970-01-01 to (year, month, day),
+/// see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let day_of_era = (z - era * 146097) as u64;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era as i64 + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+pub(crate) fn format_rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+pub(crate) fn format_monotonic(started: Instant, now: Instant) -> String {
+    format!(
+        "{:.3}",
+        now.saturating_duration_since(started).as_secs_f64()
+    )
+}
+
+pub(crate) fn format_timestamp(timestamp_format: TimestampFormat, started: Instant) -> String {
+    match timestamp_format {
+        TimestampFormat::Rfc3339 => format_rfc3339(SystemTime::now()),
+        TimestampFormat::Monotonic => format_monotonic(started, Instant::now()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    use super::civil_from_days;
+    use super::format_monotonic;
+    use super::format_rfc3339;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19509), (2023, 6, 1));
+    }
+
+    #[test]
+    fn test_format_rfc3339() {
+        assert_eq!(format_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_rfc3339(UNIX_EPOCH + Duration::from_millis(1_685_622_896_789)),
+            "2023-06-01T12:34:56.789Z"
+        );
+    }
+
+    #[test]
+    fn test_format_monotonic() {
+        let started = Instant::now();
+        assert_eq!(format_monotonic(started, started), "0.000");
+        assert_eq!(
+            format_monotonic(started, started + Duration::from_millis(12345)),
+            "12.345"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use std::io;
+
+/// Error for a malformed service spec, never worth another attempt
+pub(crate) fn invalid_input(text: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, text)
+}
+
+/// Error for a response that does not follow the protocol spoken
+pub(crate) fn protocol_error(text: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, text)
+}
+
+/// Takes the given number of bytes off the front of a response
+pub(crate) fn take<'a>(input: &mut &'a [u8], count: usize) -> Result<&'a [u8], io::Error> {
+    if input.len() < count {
+        return Err(protocol_error(String::from("Truncated response")));
+    }
+    let (taken, rest) = input.split_at(count);
+    *input = rest;
+    Ok(taken)
+}
+
+pub(crate) fn take_u16(input: &mut &[u8]) -> Result<u16, io::Error> {
+    take(input, 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub(crate) fn take_i16(input: &mut &[u8]) -> Result<i16, io::Error> {
+    take(input, 2).map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub(crate) fn take_u32(input: &mut &[u8]) -> Result<u32, io::Error> {
+    take(input, 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+pub(crate) fn take_i32(input: &mut &[u8]) -> Result<i32, io::Error> {
+    take(input, 4).map(|bytes| i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Same as [`take_i32`] for little-endian protocols like BSON
+pub(crate) fn take_i32_le(input: &mut &[u8]) -> Result<i32, io::Error> {
+    take(input, 4).map(|bytes| i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::take;
+    use super::{take_i16, take_i32, take_i32_le, take_u16, take_u32};
+
+    #[test]
+    fn test_take() {
+        let mut input: &[u8] = b"\x00\x01\xff\xfe\x00\x00\x00\x02\x02\x00\x00\x00\xff\xff\xff\xfd!";
+        assert_eq!(take_u16(&mut input).unwrap(), 1);
+        assert_eq!(take_i16(&mut input).unwrap(), -2);
+        assert_eq!(take_u32(&mut input).unwrap(), 2);
+        assert_eq!(take_i32_le(&mut input).unwrap(), 2);
+        assert_eq!(take_i32(&mut input).unwrap(), -3);
+        assert_eq!(take(&mut input, 1).unwrap(), b"!");
+
+        let error = take(&mut input, 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Truncated response");
+    }
+}
@@ -0,0 +1,307 @@
+// This file is part of the rust-for-it project.
+//
+// Copyright (c) 2023 Sebastian Pipping <sebastian@pipping.org>
+// SPDX-License-Identifier: MIT
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::network::{wait_for_service, WaitContext, WaitEvent, WaitReport, DEFAULT_RETRY_DELAY};
+
+/// Same default as the command line interface
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+type EventCallback<'a> = Box<dyn FnMut(&WaitEvent) + 'a>;
+
+/// Which services need to be available for waiting to be a success
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Policy {
+    #[default]
+    All,
+    /// Stops waiting for the other services once the first one is available
+    Any,
+}
+
+/// Outcome of waiting for all services, one report per service in order of configuration
+#[derive(Debug)]
+pub struct Report {
+    pub services: Vec<WaitReport>,
+    pub elapsed: Duration,
+    pub policy: Policy,
+}
+
+impl Report {
+    pub fn available_count(&self) -> usize {
+        self.services
+            .iter()
+            .filter(|report| report.result.is_ok())
+            .count()
+    }
+
+    pub fn is_success(&self) -> bool {
+        match self.policy {
+            Policy::All => self.available_count() == self.services.len(),
+            Policy::Any => self.available_count() > 0 || self.services.is_empty(),
+        }
+    }
+}
+
+/// Waits for services to accept TCP connections or pass other probes, all of them in parallel
+///
+/// Events are passed to the callback on the thread calling [`Waiter::wait`],
+/// so the callback needs to be neither [`Send`] nor [`Sync`].
+pub struct Waiter<'a> {
+    services: Vec<String>,
+    timeout: Option<Duration>,
+    retry_delay: Duration,
+    policy: Policy,
+    on_event: Option<EventCallback<'a>>,
+}
+
+impl Default for Waiter<'_> {
+    fn default() -> Self {
+        Waiter {
+            services: Vec::new(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            retry_delay: DEFAULT_RETRY_DELAY,
+            policy: Policy::default(),
+            on_event: None,
+        }
+    }
+}
+
+impl<'a> Waiter<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a service in "host:port" syntax, e.g. "localhost:631" or "[::1]:631",
+    /// or with the URL scheme of a registered probe, e.g. "tcp://localhost:631"
+    pub fn service<S: Into<String>>(mut self, host_and_port: S) -> Self {
+        self.services.push(host_and_port.into());
+        self
+    }
+
+    pub fn services<I, S>(mut self, services: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.services.extend(services.into_iter().map(Into::into));
+        self
+    }
+
+    /// Time to wait per service, 15 seconds by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn without_timeout(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+
+    /// Pause in between two attempts, half a second by default
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn on_event<F: FnMut(&WaitEvent) + 'a>(mut self, on_event: F) -> Self {
+        self.on_event = Some(Box::new(on_event));
+        self
+    }
+
+    pub fn wait(mut self) -> Report {
+        let timer = Instant::now();
+        let cancelled = AtomicBool::new(false);
+        let (sender, receiver) = channel::<WaitEvent>();
+
+        let services = thread::scope(|scope| {
+            let threads: Vec<_> = self
+                .services
+                .iter()
+                .map(|service| {
+                    let sender = sender.clone();
+                    let (timeout, retry_delay, cancelled) =
+                        (self.timeout, self.retry_delay, &cancelled);
+                    scope.spawn(move || {
+                        let on_event = |event| {
+                            let _ = sender.send(event);
+                        };
+                        let context = WaitContext {
+                            timeout,
+                            retry_delay,
+                            cancelled,
+                            on_event: &on_event,
+                        };
+                        wait_for_service(service, &context)
+                    })
+                })
+                .collect();
+            drop(sender);
+
+            // NOTE: This ends once all threads have dropped their sender
+            for event in receiver {
+                if self.policy == Policy::Any && matches!(event, WaitEvent::Available { .. }) {
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+                if let Some(on_event) = self.on_event.as_mut() {
+                    on_event(&event);
+                }
+            }
+
+            threads
+                .into_iter()
+                .zip(&self.services)
+                .map(|(thread, service)| {
+                    thread
+                        .join()
+                        .unwrap_or_else(|_| WaitReport::panicked(service))
+                })
+                .collect()
+        });
+
+        Report {
+            services,
+            elapsed: timer.elapsed(),
+            policy: self.policy,
+        }
+    }
+
+    /// Same as [`Waiter::wait`] without blocking the worker threads of the runtime,
+    /// see [`crate::wait_for`]
+    #[cfg(feature = "tokio")]
+    pub async fn wait_async(mut self) -> Report {
+        let on_event = |event: &WaitEvent| {
+            if let Some(on_event) = self.on_event.as_mut() {
+                on_event(event);
+            }
+        };
+        crate::asynchronous::wait_all(
+            self.services,
+            self.timeout,
+            self.retry_delay,
+            self.policy,
+            on_event,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use crate::network::{WaitEvent, WaitReport};
+    use crate::probe::register_probe_scheme;
+    use crate::testing::closed_port;
+
+    use super::Policy;
+    use super::Report;
+    use super::Waiter;
+
+    #[test]
+    fn test_report_is_success() {
+        let mut bad = WaitReport::new("bad:2");
+        bad.result = Err(io::Error::new(io::ErrorKind::TimedOut, "Time is up"));
+        let mut report = Report {
+            services: vec![WaitReport::new("good:1"), bad],
+            elapsed: Duration::ZERO,
+            policy: Policy::All,
+        };
+        assert_eq!(report.available_count(), 1);
+        assert!(!report.is_success());
+
+        report.policy = Policy::Any;
+        assert!(report.is_success());
+    }
+
+    #[test]
+    fn test_wait_for_all() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let good = listener.local_addr().unwrap().to_string();
+        let bad = format!("127.0.0.1:{}", closed_port());
+
+        let mut finished_services = Vec::new();
+        let report = Waiter::new()
+            .services([&good, &bad])
+            .timeout(Duration::from_millis(300))
+            .retry_delay(Duration::from_millis(100))
+            .on_event(|event| match event {
+                WaitEvent::Available { .. } | WaitEvent::TimedOut { .. } => {
+                    finished_services.push(event.service().to_string())
+                }
+                _ => {}
+            })
+            .wait();
+
+        assert!(!report.is_success());
+        assert_eq!(report.services.len(), 2);
+        assert_eq!(report.services[0].service, good);
+        assert!(report.services[0].result.is_ok());
+        assert_eq!(report.services[1].service, bad);
+        assert!(report.services[1].attempts >= 2);
+        assert_eq!(finished_services.len(), 2);
+        assert!(finished_services.contains(&good) && finished_services.contains(&bad));
+    }
+
+    #[test]
+    fn test_wait_for_any() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let good = listener.local_addr().unwrap().to_string();
+        let bad = format!("127.0.0.1:{}", closed_port());
+
+        let mut cancelled_services = Vec::new();
+        let report = Waiter::new()
+            .service(bad.as_str())
+            .service(good.as_str())
+            .without_timeout()
+            .retry_delay(Duration::from_millis(100))
+            .policy(Policy::Any)
+            .on_event(|event| {
+                if let WaitEvent::Cancelled { service, .. } = event {
+                    cancelled_services.push(service.clone());
+                }
+            })
+            .wait();
+
+        assert!(report.is_success());
+        assert_eq!(
+            report.services[0].result.as_ref().unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        assert!(report.services[1].result.is_ok());
+        assert_eq!(cancelled_services, vec![bad]);
+    }
+
+    #[test]
+    fn test_wait_for_panicking_service() {
+        register_probe_scheme("panicking", |_| panic!("Probe factory panicked"));
+        let report = Waiter::new().service("panicking://localhost").wait();
+
+        assert!(!report.is_success());
+        assert_eq!(report.services[0].service, "panicking://localhost");
+        assert_eq!(
+            report.services[0].failure_message.as_deref(),
+            Some("panicking://localhost failed (Waiting panicked).")
+        );
+    }
+
+    #[test]
+    fn test_wait_for_nothing() {
+        let report = Waiter::new().wait();
+        assert!(report.services.is_empty());
+        assert!(report.is_success());
+    }
+}